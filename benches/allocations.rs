@@ -0,0 +1,70 @@
+//! Counts the heap allocations made for each event, run with `cargo bench --bench allocations`
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{info, info_span};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::util::SubscriberInitExt;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const EVENTS: usize = 10_000;
+
+/// Average allocations for an event with a message and two fields, in a span with one field
+fn allocations_per_event() -> f64 {
+    let _span = info_span!("request", user = "jack").entered();
+    // Warm up anything that's reused between events
+    info!(status = 200, path = "/health", "handled request");
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..EVENTS {
+        info!(status = 200, path = "/health", "handled request");
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / EVENTS as f64
+}
+
+fn main() {
+    let report = |name: &str, allocations: f64| {
+        println!("{name:<30} {allocations:>6.2} allocations per event")
+    };
+
+    for (name, format) in [
+        ("traceon json", traceon::Format::Json),
+        ("traceon logfmt", traceon::Format::Logfmt),
+        ("traceon pretty", traceon::Format::Pretty),
+    ] {
+        let _guard = traceon::builder()
+            .format(format)
+            .writer(std::io::sink())
+            .on_thread();
+        report(name, allocations_per_event());
+    }
+
+    let _guard = tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_span_list(true)
+        .with_writer(std::io::sink)
+        .with_env_filter(EnvFilter::new("info"))
+        .set_default();
+    report("tracing_subscriber json", allocations_per_event());
+}
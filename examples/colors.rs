@@ -0,0 +1,16 @@
+use traceon::{Color, ColorMode, Style, Theme, info, warn};
+
+fn main() {
+    traceon::builder()
+        .stderr()
+        .color(ColorMode::Always)
+        .theme(Theme {
+            info: Style::new().fg(Color::Cyan),
+            string: Style::new().fg(Color::Green),
+            ..Theme::default()
+        })
+        .on();
+
+    info!(user = "jack", attempts = 3, admin = false, "logged in");
+    warn!(retry = true, "slow response");
+}
@@ -0,0 +1,27 @@
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+struct ConfigError {
+    source: std::io::Error,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to load config")
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn main() {
+    traceon::builder().on();
+
+    let error = ConfigError {
+        source: std::fs::read("missing.toml").unwrap_err(),
+    };
+    traceon::error!(error = &error as &dyn Error, "startup failed");
+}
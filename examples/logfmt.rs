@@ -0,0 +1,12 @@
+use traceon::{info, info_span};
+
+fn main() {
+    traceon::builder().logfmt().message_key("msg").on();
+
+    let _span = info_span!("request", user = "jack clayton").entered();
+    info!(
+        query = "name=\"traceon\"",
+        lines = "first\nsecond",
+        "logged in"
+    );
+}
@@ -0,0 +1,9 @@
+use traceon::{info, info_span};
+
+fn main() {
+    traceon::builder().nested_json().on();
+
+    let _request = info_span!("request", id = 5, user = "jack").entered();
+    let _query = info_span!("query", id = 10).entered();
+    info!(rows = 2, "query complete");
+}
@@ -0,0 +1,11 @@
+use traceon::{NonBlocking, QueueFull, info};
+
+fn main() {
+    // Hold onto the guard until the end of main so the queue is flushed on shutdown
+    let (writer, _guard) = NonBlocking::new(std::io::stdout(), 10_000, QueueFull::DropOldest);
+    traceon::builder().json().non_blocking(writer).on();
+
+    for i in 0..5 {
+        info!(i, "written on the traceon-writer thread");
+    }
+}
@@ -0,0 +1,16 @@
+use traceon::{Redact, info, instrument};
+
+#[instrument]
+fn login(user: &str, password: &str, card_number: u64) {
+    info!(session_token = "abc123", "logged in");
+}
+
+fn main() {
+    traceon::builder()
+        .redact(&["password", "authorization"], Redact::Mask)
+        .redact(&["*token*"], Redact::Remove)
+        .redact(&["card_number"], Redact::KeepLast(4))
+        .on();
+
+    login("jack", "hunter2", 4111111111111111);
+}
@@ -0,0 +1,21 @@
+use std::time::Duration;
+use traceon::{RollingFile, Rotation, info};
+
+fn main() -> std::io::Result<()> {
+    let file = RollingFile::new("logs", "app.log")
+        // Rotate when the file would go over 1 KiB
+        .max_size(1024)
+        // And at the start of every hour
+        .rotation(Rotation::Hourly)
+        // Keep the 3 most recently rotated files
+        .max_files(3)
+        // Delete rotated files older than a day
+        .max_age(Duration::from_secs(24 * 60 * 60));
+
+    traceon::builder().json().rolling_file(file)?.on();
+
+    for i in 0..50 {
+        info!(i, "rotated every 1 KiB");
+    }
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use traceon::{Format, Level, Route, info, warn};
+
+fn main() {
+    traceon::builder()
+        // Warnings and errors go to stderr
+        .route(Route::writer(std::io::stderr()).level(Level::WARN))
+        // Everything from the `dependency` target is written as json
+        .route(
+            Route::writer(std::io::stdout())
+                .target("dependency")
+                .format(Format::Json),
+        )
+        // Everything else goes to stdout with pretty formatting
+        .writer(std::io::stdout())
+        .on();
+
+    warn!("written to stderr");
+    info!(target: "dependency::client", "written to stdout as json");
+    info!("written to stdout");
+}
@@ -0,0 +1,8 @@
+use traceon::{SpanEvents, info, info_span};
+
+fn main() {
+    traceon::builder().span_events(SpanEvents::NewAndClose).on();
+
+    let span = info_span!("request", id = 5);
+    span.in_scope(|| info!("handling request"));
+}
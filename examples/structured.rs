@@ -0,0 +1,30 @@
+use serde::Serialize;
+use traceon::{info, info_span, structured};
+
+#[derive(Serialize)]
+struct User {
+    name: &'static str,
+    roles: Vec<&'static str>,
+    address: Address,
+}
+
+#[derive(Serialize)]
+struct Address {
+    city: &'static str,
+    postcode: u32,
+}
+
+fn main() {
+    traceon::builder().on();
+
+    let user = User {
+        name: "jack",
+        roles: vec!["admin", "dev"],
+        address: Address {
+            city: "Sydney",
+            postcode: 2000,
+        },
+    };
+    let _span = info_span!("request", sizes = ?structured([10, 15, 20])).entered();
+    info!(user = ?structured(&user), "logged in");
+}
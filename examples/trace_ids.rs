@@ -0,0 +1,17 @@
+use traceon::{TraceParent, info, info_span};
+
+fn main() {
+    traceon::builder().json().trace_ids().on();
+
+    // Continue a trace from an incoming request header
+    let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let _request = info_span!("request", traceparent = header).entered();
+    let _query = info_span!("query").entered();
+    info!("running query");
+
+    // Pass the current context on to a child process
+    let mut command = std::process::Command::new("echo");
+    if let Some(traceparent) = TraceParent::current() {
+        command.env("TRACEPARENT", traceparent.to_string());
+    }
+}
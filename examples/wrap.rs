@@ -0,0 +1,25 @@
+use traceon::{Wrap, info};
+
+// The fields are only read through `Debug`
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn main() {
+    traceon::builder()
+        .wrap(Wrap::Width(50))
+        .pretty_json_strings()
+        .alternate_debug()
+        .on();
+
+    info!(
+        note = "the quick brown fox jumps over the lazy dog, then does it all over again",
+        body = r#"{"user":"jack","roles":["admin"]}"#,
+        point = ?Point { x: 1, y: 2 },
+        名前 = "ジャック",
+        "layout"
+    );
+}
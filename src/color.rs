@@ -0,0 +1,184 @@
+use nu_ansi_term::{Color, Style};
+use std::fmt::Write;
+use tracing::Level;
+
+/// When to color the output, set with `color()` on the builder
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Color when writing to stdout or stderr and it's a terminal, `NO_COLOR` turns colors off and `FORCE_COLOR`
+    /// turns them on for any writer
+    #[default]
+    Auto,
+    /// Always color, even when writing to a file or buffer
+    Always,
+    /// Never color
+    Never,
+}
+
+/**
+Styles used to color the output, set with `theme()` on the builder. Pretty output colors the headline and fields,
+json is only colored with `colored_json()`:
+```
+use traceon::{Color, Style, Theme};
+
+traceon::builder()
+    .theme(Theme {
+        info: Style::new().fg(Color::Cyan),
+        key: Style::new().fg(Color::LightGray),
+        string: Style::new().fg(Color::Green),
+        ..Theme::default()
+    })
+    .on();
+```
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    /// Level and message of trace events
+    pub trace: Style,
+    /// Level and message of debug events
+    pub debug: Style,
+    /// Level and message of info events
+    pub info: Style,
+    /// Level and message of warnings
+    pub warn: Style,
+    /// Level and message of errors
+    pub error: Style,
+    /// The time at the start of the pretty headline
+    pub time: Style,
+    /// Field keys, including the keys of nested maps
+    pub key: Style,
+    /// String values
+    pub string: Style,
+    /// Number values
+    pub number: Style,
+    /// `true` and `false` values
+    pub boolean: Style,
+    /// `null` values
+    pub null: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            trace: Style::new().fg(Color::Purple),
+            debug: Style::new().fg(Color::Blue),
+            info: Style::new().fg(Color::Green),
+            warn: Style::new().fg(Color::Yellow),
+            error: Style::new().fg(Color::Red),
+            time: Style::new().dimmed(),
+            key: Style::new().fg(Color::Blue).bold(),
+            string: Style::new(),
+            number: Style::new().fg(Color::Cyan),
+            boolean: Style::new().fg(Color::Yellow),
+            null: Style::new().dimmed(),
+        }
+    }
+}
+
+impl Theme {
+    /// Style for the level and message of an event
+    pub(crate) fn level(&self, level: &Level) -> Style {
+        match *level {
+            Level::TRACE => self.trace,
+            Level::DEBUG => self.debug,
+            Level::INFO => self.info,
+            Level::WARN => self.warn,
+            Level::ERROR => self.error,
+        }
+    }
+
+    /// Style for a value based on its json type, maps and sequences aren't styled as their entries are
+    pub(crate) fn value(&self, value: &serde_json::Value) -> Style {
+        match value {
+            serde_json::Value::String(_) => self.string,
+            serde_json::Value::Number(_) => self.number,
+            serde_json::Value::Bool(_) => self.boolean,
+            serde_json::Value::Null => self.null,
+            _ => Style::new(),
+        }
+    }
+}
+
+/// Write `text` painted with `style`, leaving it untouched if colors are off or the style is plain
+pub(crate) fn paint(out: &mut String, style: Option<Style>, text: &str) {
+    match style {
+        Some(style) if style != Style::new() => {
+            // Writing to a string can't fail
+            let _ = write!(out, "{}", style.paint(text));
+        }
+        _ => out.push_str(text),
+    }
+}
+
+/// Colors from `NO_COLOR` and `FORCE_COLOR`, `NO_COLOR` wins if both are set. `None` if neither is set, so the
+/// writer decides.
+pub(crate) fn env_colors() -> Option<bool> {
+    let set = |variable| std::env::var_os(variable).is_some_and(|value| !value.is_empty());
+    if set("NO_COLOR") {
+        Some(false)
+    } else if set("FORCE_COLOR") {
+        Some(std::env::var_os("FORCE_COLOR").is_some_and(|value| value != "0"))
+    } else {
+        None
+    }
+}
+
+/// Color a line of compact json like jq, keys and each type of value get their style from the theme
+pub(crate) fn paint_json(json: &[u8], theme: &Theme) -> Vec<u8> {
+    let Ok(json) = std::str::from_utf8(json) else {
+        return json.to_vec();
+    };
+    let bytes = json.as_bytes();
+    let mut painted = String::with_capacity(json.len() * 2);
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let style = match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() {
+                    match bytes[i] {
+                        b'\\' => i += 2,
+                        b'"' => {
+                            i += 1;
+                            break;
+                        }
+                        _ => i += 1,
+                    }
+                }
+                i = i.min(bytes.len());
+                if bytes.get(i) == Some(&b':') {
+                    theme.key
+                } else {
+                    theme.string
+                }
+            }
+            b't' | b'f' | b'n' => {
+                while i < bytes.len() && bytes[i].is_ascii_alphabetic() {
+                    i += 1;
+                }
+                if bytes[start] == b'n' {
+                    theme.null
+                } else {
+                    theme.boolean
+                }
+            }
+            b'-' | b'0'..=b'9' => {
+                while i < bytes.len()
+                    && matches!(bytes[i], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+                {
+                    i += 1;
+                }
+                theme.number
+            }
+            // Punctuation outside of strings is always ascii
+            _ => {
+                painted.push(bytes[i] as char);
+                i += 1;
+                continue;
+            }
+        };
+        paint(&mut painted, Some(style), &json[start..i]);
+    }
+    painted.into_bytes()
+}
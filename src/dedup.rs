@@ -0,0 +1,160 @@
+use crate::traceon::JsonStorage;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{Metadata, callsite::Identifier};
+
+/**
+Settings to stop repeated events flooding the output, add it to the builder with `dedup()`. An event is a repeat if
+it comes from the same callsite with the same message, or the same values for the selected fields. The first event
+is written and repeats within the window are counted, then once the window has closed, the next event from anywhere
+first writes a follow-up record with the fields from the last repeat and a `suppressed_count`. Windows that are still
+open when the layer is dropped, e.g. when the guard from `on_thread()` is dropped, write their follow-up straight away.
+Span fields aren't included in the follow-up, as the span may have closed.
+
+```
+use std::time::Duration;
+use traceon::Dedup;
+
+traceon::builder()
+    .dedup(Dedup::new(Duration::from_secs(10)))
+    .on();
+
+for _ in 0..1000 {
+    traceon::warn!("retrying connection");
+}
+```
+*/
+#[derive(Clone, Debug)]
+pub struct Dedup {
+    window: Duration,
+    fields: Option<Vec<&'static str>>,
+}
+
+impl Dedup {
+    /// Count repeats of an event for `window` after it's written
+    pub fn new(window: Duration) -> Dedup {
+        Dedup {
+            window,
+            fields: None,
+        }
+    }
+
+    /// Compare these fields instead of the message to decide if an event is a repeat, e.g. `&["url"]` so retries to
+    /// different urls are all written even if they have the same message
+    #[must_use]
+    pub fn fields(mut self, fields: &[&'static str]) -> Dedup {
+        self.fields = Some(fields.to_vec());
+        self
+    }
+}
+
+/// A callsite and the values that make an event a repeat
+type Key = (Identifier, Vec<Option<String>>);
+
+struct Window {
+    start: Instant,
+    suppressed: u64,
+    metadata: &'static Metadata<'static>,
+    /// Fields from the last repeat for the follow-up record
+    last: Option<JsonStorage<'static>>,
+}
+
+/// Repeats that were suppressed in a window that has now closed
+pub(crate) struct Suppressed {
+    pub metadata: &'static Metadata<'static>,
+    pub fields: JsonStorage<'static>,
+    pub count: u64,
+}
+
+/// Open windows shared by every clone of the layer
+#[derive(Clone)]
+pub(crate) struct DedupState {
+    settings: Dedup,
+    windows: Arc<Mutex<HashMap<Key, Window>>>,
+}
+
+impl DedupState {
+    pub fn new(settings: Dedup) -> DedupState {
+        DedupState {
+            settings,
+            windows: Arc::default(),
+        }
+    }
+
+    /// Returns if the event should be written, and the repeats from any windows that have closed
+    pub fn check(
+        &self,
+        metadata: &'static Metadata<'static>,
+        fields: &JsonStorage<'static>,
+    ) -> (bool, Vec<Suppressed>) {
+        let now = Instant::now();
+        let values = match &self.settings.fields {
+            Some(names) => names
+                .iter()
+                .map(|name| fields.values.get(name).map(Value::to_string))
+                .collect(),
+            None => vec![fields.values.get("message").map(Value::to_string)],
+        };
+        let key = (metadata.callsite(), values);
+
+        let mut windows = self.windows.lock().unwrap();
+        let mut closed = Vec::new();
+        windows.retain(|_, window| {
+            if now.duration_since(window.start) < self.settings.window {
+                return true;
+            }
+            if let Some(last) = window.last.take() {
+                closed.push(Suppressed {
+                    metadata: window.metadata,
+                    fields: last,
+                    count: window.suppressed,
+                });
+            }
+            false
+        });
+
+        let write = match windows.get_mut(&key) {
+            Some(window) => {
+                window.suppressed += 1;
+                window.last = Some(fields.clone());
+                false
+            }
+            None => {
+                windows.insert(
+                    key,
+                    Window {
+                        start: now,
+                        suppressed: 0,
+                        metadata,
+                        last: None,
+                    },
+                );
+                true
+            }
+        };
+        (write, closed)
+    }
+
+    /// The repeats from every window that hasn't closed yet, if this is the last clone of the state
+    pub fn pending(self) -> Vec<Suppressed> {
+        let Some(windows) = Arc::into_inner(self.windows) else {
+            return Vec::new();
+        };
+        windows
+            .into_inner()
+            .unwrap()
+            .into_values()
+            .filter_map(|window| {
+                Some(Suppressed {
+                    metadata: window.metadata,
+                    fields: window.last?,
+                    count: window.suppressed,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,68 @@
+use crate::json::{Json, Object};
+use crate::traceon::Keys;
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// Version of the Elastic Common Schema the records conform to
+pub(crate) const VERSION: &str = "8.11.0";
+
+/// ECS fields for the built-in keys, dots are expanded into nested objects when the record is written
+pub(crate) const KEYS: Keys = Keys {
+    time: "@timestamp",
+    level: "log.level",
+    message: "message",
+    module: "log.logger",
+    file: "log.origin.file.name",
+    span: "span.name",
+    fields: "fields",
+    spans: "spans",
+    name: "name",
+};
+
+pub(crate) const LINE_KEY: &str = "log.origin.file.line";
+pub(crate) const VERSION_KEY: &str = "ecs.version";
+
+/// ECS field for a trace field, `None` for trace fields it has no field for
+pub(crate) fn trace_field(name: &str, value: &str) -> Option<(&'static str, Value)> {
+    match name {
+        "trace_id" => Some(("trace.id", Value::from(value))),
+        "span_id" => Some(("span.id", Value::from(value))),
+        _ => None,
+    }
+}
+
+/// Fields with a dotted key or a map value are their own namespace e.g. `http.request.method`, anything else goes
+/// under `labels`
+pub(crate) fn field_key<'a>(key: Cow<'a, str>, value: &Value) -> Cow<'a, str> {
+    if key.contains('.') || value.is_object() {
+        key
+    } else {
+        Cow::Owned(format!("labels.{key}"))
+    }
+}
+
+/// Expand dotted keys into nested objects, e.g. `log.level` becomes `{"log":{"level":...}}`, maps written to the same
+/// key are merged
+pub(crate) fn nest<'a>(entries: impl IntoIterator<Item = (&'a str, &'a Value)>) -> Object<'a> {
+    let mut record = Object::default();
+    for (key, value) in entries {
+        let (parents, leaf) = match key.rsplit_once('.') {
+            Some((parents, leaf)) => (Some(parents), leaf),
+            None => (None, key),
+        };
+        let mut object = &mut record;
+        for part in parents.into_iter().flat_map(|parents| parents.split('.')) {
+            object = object.object(part);
+        }
+        match value {
+            Value::Object(map) if object.get(leaf).is_some_and(Json::is_object) => {
+                let existing = object.object(leaf);
+                for (key, value) in map {
+                    existing.insert(key.as_str(), Json::from(value));
+                }
+            }
+            _ => object.insert(leaf, Json::from(value)),
+        }
+    }
+    record
+}
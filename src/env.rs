@@ -0,0 +1,251 @@
+use crate::{
+    Case, ColorMode, Format, LevelFormat, SecondsFormat, SpanEvents, SpanFormat, TimeFormat,
+    TimeZone,
+};
+use std::{env::VarError, fmt, sync::Arc};
+
+/// Error returned when a `TRACEON_*` environment variable has an invalid value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvError {
+    /// Name of the environment variable
+    pub variable: &'static str,
+    /// The invalid value
+    pub value: String,
+    /// Description of the valid values
+    pub expected: &'static str,
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid value `{}` for {}, expected {}",
+            self.value, self.variable, self.expected
+        )
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+/// Settings read from the `TRACEON_*` environment variables, `None` when a variable isn't set
+#[derive(Default)]
+pub(crate) struct EnvConfig {
+    pub format: Option<Format>,
+    /// Project of the Cloud Trace traces with `google_cloud:<project id>`
+    pub google_project: Option<Arc<str>>,
+    pub file: Option<bool>,
+    pub module: Option<bool>,
+    pub span_format: Option<SpanFormat>,
+    pub span_events: Option<SpanEvents>,
+    pub case: Option<Case>,
+    pub time: Option<TimeFormat>,
+    pub timezone: Option<TimeZone>,
+    pub level: Option<LevelFormat>,
+    pub message_key: Option<&'static str>,
+    pub color: Option<ColorMode>,
+}
+
+impl EnvConfig {
+    /// Read and validate all the `TRACEON_*` environment variables
+    pub fn read() -> Result<EnvConfig, EnvError> {
+        let format = parse(
+            "TRACEON_FORMAT",
+            "one of pretty, json, nested_json, logfmt, ecs, google_cloud, or google_cloud:<project id>",
+            |value| match value.as_str() {
+                "pretty" => Some((Format::Pretty, None)),
+                "json" => Some((Format::Json, None)),
+                "nested_json" => Some((Format::NestedJson, None)),
+                "logfmt" => Some((Format::Logfmt, None)),
+                "ecs" => Some((Format::Ecs, None)),
+                "google_cloud" => Some((Format::GoogleCloud, None)),
+                _ => value
+                    .strip_prefix("google_cloud:")
+                    .filter(|project_id| !project_id.is_empty())
+                    .map(|project_id| (Format::GoogleCloud, Some(Arc::from(project_id)))),
+            },
+        )?;
+        Ok(EnvConfig {
+            google_project: format
+                .as_ref()
+                .and_then(|(_, project_id)| project_id.clone()),
+            format: format.map(|(format, _)| format),
+            file: parse("TRACEON_FILE", "true or false", parse_bool)?,
+            module: parse("TRACEON_MODULE", "true or false", parse_bool)?,
+            span_format: parse(
+                "TRACEON_SPAN",
+                "one of none, overwrite, join, or join:<characters>",
+                |value| match value.as_str() {
+                    "none" => Some(SpanFormat::None),
+                    "overwrite" => Some(SpanFormat::Overwrite),
+                    "join" => Some(SpanFormat::default()),
+                    _ => value
+                        .strip_prefix("join:")
+                        .map(|chars| SpanFormat::Join(leak(chars))),
+                },
+            )?,
+            span_events: parse(
+                "TRACEON_SPAN_EVENTS",
+                "one of none, close, new_and_close, full",
+                |value| match value.as_str() {
+                    "none" => Some(SpanEvents::None),
+                    "close" => Some(SpanEvents::Close),
+                    "new_and_close" => Some(SpanEvents::NewAndClose),
+                    "full" => Some(SpanEvents::Full),
+                    _ => None,
+                },
+            )?,
+            case: parse(
+                "TRACEON_CASE",
+                "one of none, camel, pascal, snake, screaming_snake, kebab, dot",
+                |value| match value.as_str() {
+                    "none" => Some(Case::None),
+                    "camel" => Some(Case::Camel),
+                    "pascal" => Some(Case::Pascal),
+                    "snake" => Some(Case::Snake),
+                    "screaming_snake" => Some(Case::ScreamingSnake),
+                    "kebab" => Some(Case::Kebab),
+                    "dot" => Some(Case::Dot),
+                    _ => None,
+                },
+            )?,
+            time: parse(
+                "TRACEON_TIME",
+                "one of none, epoch_seconds, epoch_milliseconds, epoch_microseconds, epoch_nanoseconds, rfc2822, \
+                rfc3339, rfc3339:<secs|millis|micros|nanos|auto>[:z], pretty_time, pretty_date_time, or custom:<format>",
+                parse_time,
+            )?,
+            timezone: parse(
+                "TRACEON_TIMEZONE",
+                "one of utc, local",
+                |value| match value.as_str() {
+                    "utc" => Some(TimeZone::UTC),
+                    "local" => Some(TimeZone::Local),
+                    _ => None,
+                },
+            )?,
+            level: parse(
+                "TRACEON_LEVEL",
+                "one of none, uppercase, lowercase, number",
+                |value| match value.as_str() {
+                    "none" => Some(LevelFormat::None),
+                    "uppercase" => Some(LevelFormat::Uppercase),
+                    "lowercase" => Some(LevelFormat::Lowercase),
+                    "number" => Some(LevelFormat::Number),
+                    _ => None,
+                },
+            )?,
+            message_key: match var("TRACEON_MESSAGE_KEY", "a non-empty key")? {
+                Some(value) if value.trim().is_empty() => {
+                    return Err(EnvError {
+                        variable: "TRACEON_MESSAGE_KEY",
+                        value,
+                        expected: "a non-empty key",
+                    });
+                }
+                Some(value) => Some(leak(value.trim())),
+                None => None,
+            },
+            color: parse(
+                "TRACEON_COLOR",
+                "one of auto, always, never",
+                |value| match value.as_str() {
+                    "auto" => Some(ColorMode::Auto),
+                    "always" => Some(ColorMode::Always),
+                    "never" => Some(ColorMode::Never),
+                    _ => None,
+                },
+            )?,
+        })
+    }
+}
+
+/// Read a variable and parse it with `parser`, which receives the value trimmed, lowercased and with `-` replaced
+/// by `_` unless the value contains a `:`, where only the part before it is normalized
+fn parse<T>(
+    variable: &'static str,
+    expected: &'static str,
+    parser: impl FnOnce(String) -> Option<T>,
+) -> Result<Option<T>, EnvError> {
+    let Some(value) = var(variable, expected)? else {
+        return Ok(None);
+    };
+    let trimmed = value.trim();
+    let normalized = match trimmed.split_once(':') {
+        Some((name, rest)) => format!("{}:{rest}", normalize(name)),
+        None => normalize(trimmed),
+    };
+    match parser(normalized) {
+        Some(parsed) => Ok(Some(parsed)),
+        None => Err(EnvError {
+            variable,
+            value,
+            expected,
+        }),
+    }
+}
+
+/// Read a variable, returning an error if it isn't valid unicode
+fn var(variable: &'static str, expected: &'static str) -> Result<Option<String>, EnvError> {
+    match std::env::var(variable) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(value)) => Err(EnvError {
+            variable,
+            value: value.to_string_lossy().to_string(),
+            expected,
+        }),
+    }
+}
+
+fn normalize(value: &str) -> String {
+    value.to_ascii_lowercase().replace('-', "_")
+}
+
+fn parse_bool(value: String) -> Option<bool> {
+    match value.as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_time(value: String) -> Option<TimeFormat> {
+    let time = match value.as_str() {
+        "none" => TimeFormat::None,
+        "epoch_seconds" => TimeFormat::EpochSeconds,
+        "epoch_milliseconds" => TimeFormat::EpochMilliseconds,
+        "epoch_microseconds" => TimeFormat::EpochMicroseconds,
+        "epoch_nanoseconds" => TimeFormat::EpochNanoseconds,
+        "rfc2822" => TimeFormat::RFC2822,
+        "rfc3339" => TimeFormat::RFC3339,
+        "pretty_time" => TimeFormat::PrettyTime,
+        "pretty_date_time" => TimeFormat::PrettyDateTime,
+        _ => {
+            if let Some(format) = value.strip_prefix("custom:") {
+                return Some(TimeFormat::CustomFormat(leak(format)));
+            }
+            let options = value.strip_prefix("rfc3339:")?;
+            let (seconds, use_z) = match options.split_once(':') {
+                Some((seconds, "z" | "Z")) => (seconds, true),
+                Some(_) => return None,
+                None => (options, false),
+            };
+            let seconds = match normalize(seconds).as_str() {
+                "secs" => SecondsFormat::Secs,
+                "millis" => SecondsFormat::Millis,
+                "micros" => SecondsFormat::Micros,
+                "nanos" => SecondsFormat::Nanos,
+                "auto" => SecondsFormat::AutoSi,
+                _ => return None,
+            };
+            TimeFormat::RFC3339Options(seconds, use_z)
+        }
+    };
+    Some(time)
+}
+
+/// The builder takes `&'static str` for keys and formats, the environment is only read once at startup so leaking is
+/// fine
+fn leak(value: &str) -> &'static str {
+    Box::leak(value.to_string().into_boxed_str())
+}
@@ -0,0 +1,136 @@
+use std::{env::VarError, fmt};
+use tracing::{Level, subscriber::SetGlobalDefaultError};
+use tracing_subscriber::{
+    EnvFilter,
+    filter::{Directive, LevelFilter, ParseError},
+};
+
+/// Where the filter directives come from, set with the builder's filter methods
+#[derive(Clone, Debug)]
+pub(crate) struct FilterConfig {
+    /// Directives used when the environment variable isn't set
+    pub directives: String,
+    /// Typed levels for targets added to `directives`
+    pub targets: Vec<(String, Level)>,
+    /// Environment variable that replaces `directives` and `targets` when set, `None` to ignore the environment
+    pub env_var: Option<&'static str>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            directives: "info".to_string(),
+            targets: Vec::new(),
+            env_var: Some(EnvFilter::DEFAULT_ENV),
+        }
+    }
+}
+
+impl FilterConfig {
+    /// Build the filter from the environment variable if it's set, otherwise from the directives and target levels
+    pub fn build(&self) -> Result<EnvFilter, FilterError> {
+        if let Some(variable) = self.env_var {
+            match std::env::var(variable) {
+                Ok(directives) => return parse(&directives, Some(variable)),
+                Err(VarError::NotPresent) => (),
+                Err(VarError::NotUnicode(directives)) => {
+                    return parse(&directives.to_string_lossy(), Some(variable));
+                }
+            }
+        }
+        let mut filter = parse(&self.directives, None)?;
+        for (target, level) in &self.targets {
+            let directive = format!("{target}={}", LevelFilter::from_level(*level));
+            filter = filter.add_directive(directive.parse::<Directive>().map_err(|error| {
+                FilterError {
+                    variable: None,
+                    directives: directive,
+                    error,
+                }
+            })?);
+        }
+        Ok(filter)
+    }
+}
+
+fn parse(directives: &str, variable: Option<&'static str>) -> Result<EnvFilter, FilterError> {
+    EnvFilter::builder()
+        .parse(directives)
+        .map_err(|error| FilterError {
+            variable,
+            directives: directives.to_string(),
+            error,
+        })
+}
+
+/// Error returned when filter directives can't be parsed
+#[derive(Debug)]
+pub struct FilterError {
+    /// Name of the environment variable the directives came from, `None` if they came from the builder
+    pub variable: Option<&'static str>,
+    /// The invalid directives
+    pub directives: String,
+    error: ParseError,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.variable {
+            Some(variable) => write!(
+                f,
+                "invalid filter directives `{}` in {variable}: {}",
+                self.directives, self.error
+            ),
+            None => write!(
+                f,
+                "invalid filter directives `{}`: {}",
+                self.directives, self.error
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Error returned from `try_on`
+#[derive(Debug)]
+pub enum OnError {
+    /// The filter directives from the builder or the environment couldn't be parsed
+    Filter(FilterError),
+    /// A global default subscriber has already been set
+    AlreadySet(SetGlobalDefaultError),
+}
+
+impl fmt::Display for OnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OnError::Filter(error) => error.fmt(f),
+            OnError::AlreadySet(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for OnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OnError::Filter(error) => Some(error),
+            OnError::AlreadySet(error) => Some(error),
+        }
+    }
+}
+
+impl From<FilterError> for OnError {
+    fn from(error: FilterError) -> Self {
+        OnError::Filter(error)
+    }
+}
+
+impl From<SetGlobalDefaultError> for OnError {
+    fn from(error: SetGlobalDefaultError) -> Self {
+        OnError::AlreadySet(error)
+    }
+}
@@ -0,0 +1,68 @@
+use crate::json::{Json, Object};
+use crate::traceon::Keys;
+use serde_json::{Value, json};
+use tracing::{Level, Metadata};
+
+/// Cloud Logging reads its special fields by their exact keys
+pub(crate) const KEYS: Keys = Keys {
+    time: "time",
+    level: "severity",
+    message: "message",
+    module: "module",
+    file: "file",
+    span: "span",
+    fields: "fields",
+    spans: "spans",
+    name: "name",
+};
+
+pub(crate) const SOURCE_LOCATION_KEY: &str = "logging.googleapis.com/sourceLocation";
+
+/// Cloud Logging severity for a level, tracing has no levels for `DEFAULT` or `CRITICAL`
+pub(crate) fn severity(level: &Level) -> &'static str {
+    match *level {
+        Level::TRACE | Level::DEBUG => "DEBUG",
+        Level::INFO => "INFO",
+        Level::WARN => "WARNING",
+        Level::ERROR => "ERROR",
+    }
+}
+
+/// The file, line and function of the callsite, Cloud Logging has no module so the module path is the function
+pub(crate) fn source_location(metadata: &Metadata<'_>) -> Object<'static> {
+    let mut location = Object::default();
+    if let Some(file) = metadata.file() {
+        location.insert("file", Json::from(Value::from(file)));
+    }
+    // The line is an int64, which is a string in the json mapping of the Cloud Logging API
+    if let Some(line) = metadata.line() {
+        location.insert("line", Json::from(Value::from(line.to_string())));
+    }
+    if let Some(module) = metadata.module_path() {
+        location.insert("function", Json::from(Value::from(module)));
+    }
+    location
+}
+
+/// Key and value Cloud Logging uses to link a record to Cloud Trace, `None` for trace fields it has no key for
+pub(crate) fn trace_field(
+    name: &str,
+    value: &str,
+    project_id: Option<&str>,
+) -> Option<(&'static str, Value)> {
+    match name {
+        "trace_id" => Some((
+            "logging.googleapis.com/trace",
+            match project_id {
+                Some(project_id) => json!(format!("projects/{project_id}/traces/{value}")),
+                None => json!(value),
+            },
+        )),
+        "span_id" => Some(("logging.googleapis.com/spanId", json!(value))),
+        "trace_flags" => Some((
+            "logging.googleapis.com/trace_sampled",
+            json!(u8::from_str_radix(value, 16).is_ok_and(|flags| flags & 1 == 1)),
+        )),
+        _ => None,
+    }
+}
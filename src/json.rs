@@ -0,0 +1,131 @@
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// A json value built for a record, objects keep their keys in the order they're inserted where a `serde_json::Map`
+/// would sort them
+pub(crate) enum Json<'a> {
+    Value(Cow<'a, Value>),
+    Object(Object<'a>),
+    Array(Vec<Json<'a>>),
+}
+
+/// Keys and values of a json object in the order they're inserted
+#[derive(Default)]
+pub(crate) struct Object<'a>(Vec<(Cow<'a, str>, Json<'a>)>);
+
+impl<'a> Json<'a> {
+    /// The value if it's a plain json value, objects and arrays are only built for the json formats
+    pub fn as_value(&self) -> Option<&Value> {
+        match self {
+            Json::Value(value) => Some(value),
+            Json::Object(_) | Json::Array(_) => None,
+        }
+    }
+
+    /// `true` for an object or a json map
+    pub fn is_object(&self) -> bool {
+        matches!(self, Json::Object(_)) || self.as_value().is_some_and(Value::is_object)
+    }
+
+    /// This value as an object to insert into, a map is converted keeping its keys, anything else is replaced
+    fn as_object(&mut self) -> &mut Object<'a> {
+        if !matches!(self, Json::Object(_)) {
+            let object = match std::mem::replace(self, Json::Object(Object::default())) {
+                Json::Value(Cow::Borrowed(Value::Object(map))) => map
+                    .iter()
+                    .map(|(key, value)| (Cow::Borrowed(key.as_str()), Json::from(value)))
+                    .collect(),
+                Json::Value(Cow::Owned(Value::Object(map))) => map
+                    .into_iter()
+                    .map(|(key, value)| (Cow::Owned(key), Json::Value(Cow::Owned(value))))
+                    .collect(),
+                _ => Object::default(),
+            };
+            *self = Json::Object(object);
+        }
+        match self {
+            Json::Object(object) => object,
+            _ => unreachable!("the value was just made an object"),
+        }
+    }
+}
+
+impl<'a> From<&'a Value> for Json<'a> {
+    fn from(value: &'a Value) -> Self {
+        Json::Value(Cow::Borrowed(value))
+    }
+}
+
+impl From<Value> for Json<'_> {
+    fn from(value: Value) -> Self {
+        Json::Value(Cow::Owned(value))
+    }
+}
+
+impl<'a> Object<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json<'a>> {
+        self.0
+            .iter()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Insert or replace a value, a key that's replaced keeps its place
+    pub fn insert(&mut self, key: impl Into<Cow<'a, str>>, value: Json<'a>) {
+        let key = key.into();
+        match self.0.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((key, value)),
+        }
+    }
+
+    /// The object at `key`, inserted if it's missing and replacing a value that isn't an object
+    pub fn object(&mut self, key: &'a str) -> &mut Object<'a> {
+        let index = match self.0.iter().position(|(existing, _)| existing == key) {
+            Some(index) => index,
+            None => {
+                self.0
+                    .push((Cow::Borrowed(key), Json::Object(Object::default())));
+                self.0.len() - 1
+            }
+        };
+        self.0[index].1.as_object()
+    }
+}
+
+impl<'a> FromIterator<(Cow<'a, str>, Json<'a>)> for Object<'a> {
+    fn from_iter<I: IntoIterator<Item = (Cow<'a, str>, Json<'a>)>>(iter: I) -> Self {
+        Object(iter.into_iter().collect())
+    }
+}
+
+impl Serialize for Json<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Json::Value(value) => value.serialize(serializer),
+            Json::Object(object) => object.serialize(serializer),
+            Json::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+        }
+    }
+}
+
+impl Serialize for Object<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
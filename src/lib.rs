@@ -1,9 +1,42 @@
 #![warn(missing_docs)]
 #![doc = include_str!("../README.md")]
+mod color;
+mod dedup;
+mod ecs;
+mod env;
+mod filter;
+mod google_cloud;
+mod json;
+mod non_blocking;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+mod pretty;
+mod redact;
+mod reload;
+mod rolling;
+mod route;
+mod structured;
+mod trace_context;
 mod traceon;
+pub use crate::color::{ColorMode, Theme};
+pub use crate::dedup::Dedup;
+pub use crate::env::EnvError;
+pub use crate::filter::{FilterError, OnError};
+pub use crate::non_blocking::{NonBlocking, QueueFull, WriterGuard};
+pub use crate::pretty::Wrap;
+pub use crate::redact::Redact;
+pub use crate::reload::{ReloadError, ReloadHandle};
+pub use crate::rolling::{RollingFile, RollingFileWriter, Rotation};
+pub use crate::route::Route;
+pub use crate::structured::{Structured, structured};
+pub use crate::trace_context::TraceParent;
 use crate::traceon::Traceon;
-pub use crate::traceon::{Case, JoinFields, LevelFormat, SpanFormat, TimeFormat, TimeZone};
+pub use crate::traceon::{
+    Case, Format, JoinFields, Keys, LevelFormat, SpanEvents, SpanFormat, TimeFormat, TimeZone,
+    TraceKeys,
+};
 pub use chrono::SecondsFormat;
+pub use nu_ansi_term::{Color, Style};
 use tracing::subscriber::DefaultGuard;
 pub use tracing::{
     Instrument, Level, debug, debug_span, error, error_span, event, info, info_span, instrument,
@@ -49,9 +82,9 @@ json output:
 {
     "timestamp": "2023-01-01T12:58:49Z",
     "level": 30,
+    "message": "a simple message",
     "module": "builder",
-    "file": "examples/builder.rs:32",
-    "message": "a simple message"
+    "file": "examples/builder.rs:32"
 }
 ```
 */
@@ -59,6 +92,42 @@ pub fn builder() -> Traceon {
     Traceon::default()
 }
 
+/**
+Returns a builder with the defaults overridden by any `TRACEON_*` environment variables that are set, anything set on
+the builder afterwards overrides the environment, use `env()` on the builder instead if the environment should
+override settings made in code:
+```
+// TRACEON_FORMAT=json will switch to json output
+traceon::from_env()?.file().on();
+traceon::info!("configured from the environment");
+# Ok::<(), traceon::EnvError>(())
+```
+
+| Variable              | Values                                                                                   |
+|-----------------------|------------------------------------------------------------------------------------------|
+| `TRACEON_FORMAT`      | `pretty`, `json`, `nested_json`, `logfmt`, `ecs`, `google_cloud`, `google_cloud:<project id>` |
+| `TRACEON_FILE`        | `true`, `false`                                                                          |
+| `TRACEON_MODULE`      | `true`, `false`                                                                          |
+| `TRACEON_SPAN`        | `none`, `overwrite`, `join`, `join:<characters>`                                         |
+| `TRACEON_SPAN_EVENTS` | `none`, `close`, `new_and_close`, `full`                                                 |
+| `TRACEON_CASE`        | `none`, `camel`, `pascal`, `snake`, `screaming_snake`, `kebab`, `dot`                    |
+| `TRACEON_TIME`        | `none`, `epoch_seconds`, `epoch_milliseconds`, `epoch_microseconds`, `epoch_nanoseconds`, `rfc2822`, `rfc3339`, `rfc3339:<secs\|millis\|micros\|nanos\|auto>[:z]`, `pretty_time`, `pretty_date_time`, `custom:<format>` |
+| `TRACEON_TIMEZONE`    | `utc`, `local`                                                                           |
+| `TRACEON_LEVEL`       | `none`, `uppercase`, `lowercase`, `number`                                               |
+| `TRACEON_MESSAGE_KEY` | any non-empty key                                                                        |
+| `TRACEON_COLOR`       | `auto`, `always`, `never`                                                                |
+
+Values are case insensitive and `-` can be used instead of `_`, `true` and `false` can also be `1` and `0`.
+
+# Errors
+Returns an `EnvError` naming the variable and the valid values if any variable is set to an invalid value
+*/
+pub fn from_env() -> Result<Traceon, EnvError> {
+    let mut traceon = Traceon::default();
+    traceon.env()?;
+    Ok(traceon)
+}
+
 /**
 Turns on the pretty defaults which is local time with no date, where all the span fields are new indented lines, activating it globally on all threads.
 
@@ -108,8 +177,8 @@ info!("second subscriber")
 output:
 ```text
 11:58:50 INFO first subscriber
-    field: temp
     span:  span_with_field
+    field: temp
 ```
 
 ```json
@@ -180,8 +249,8 @@ info!("second subscriber")
 output:
 ```text
 11:58:50 INFO first subscriber
-    field: temp
     span:  span_with_field
+    field: temp
 
 {
   "time": "2023-01-02T04:59:00.841691+00:00",
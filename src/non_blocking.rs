@@ -0,0 +1,259 @@
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+};
+use tracing::{
+    Level, Metadata,
+    callsite::{Callsite, Identifier},
+    field::FieldSet,
+    metadata::Kind,
+    subscriber::Interest,
+};
+
+/// What to do with a new record when the queue of a `NonBlocking` writer is full
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum QueueFull {
+    #[default]
+    /// Block the thread that is logging until the writer thread makes room in the queue
+    Block,
+    /// Drop the new record and keep everything already in the queue
+    DropNewest,
+    /// Drop the oldest record in the queue to make room for the new record
+    DropOldest,
+}
+
+/**
+A writer that puts formatted records into a bounded queue, which is drained by a dedicated writer thread so logging
+never waits on a slow disk or pipe. Pass it to the builder with `non_blocking()`, and hold onto the returned
+`WriterGuard` until the program exits so the queue is flushed on shutdown:
+```
+use traceon::{NonBlocking, QueueFull};
+
+let (writer, _guard) = NonBlocking::new(std::io::stdout(), 10_000, QueueFull::DropOldest);
+traceon::builder().json().non_blocking(writer).on();
+traceon::info!("written on another thread");
+```
+*/
+#[derive(Clone)]
+pub struct NonBlocking {
+    shared: Arc<Shared>,
+}
+
+/// Flushes the queue of a `NonBlocking` writer and stops its thread when dropped
+#[must_use = "dropping the guard will flush and stop the writer thread straight away"]
+pub struct WriterGuard {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Builds the record reporting how many records were dropped, in the format of the layer that writes to the queue
+pub(crate) type Reporter = Arc<dyn Fn(u64) -> Option<Vec<u8>> + Send + Sync>;
+
+struct Shared {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    queue_full: QueueFull,
+    dropped: AtomicU64,
+    unreported: AtomicU64,
+    /// Set by the first layer that writes to the queue, and taken when the guard is dropped
+    reporter: Mutex<Option<Reporter>>,
+    has_reporter: AtomicBool,
+}
+
+struct State {
+    records: VecDeque<Vec<u8>>,
+    closed: bool,
+}
+
+impl NonBlocking {
+    /// Spawn a thread that writes to `writer`, queueing up to `capacity` records before applying the `queue_full`
+    /// policy
+    pub fn new(
+        writer: impl Write + Send + 'static,
+        capacity: usize,
+        queue_full: QueueFull,
+    ) -> (NonBlocking, WriterGuard) {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                records: VecDeque::new(),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            queue_full,
+            dropped: AtomicU64::new(0),
+            unreported: AtomicU64::new(0),
+            reporter: Mutex::new(None),
+            has_reporter: AtomicBool::new(false),
+        });
+        let worker = shared.clone();
+        let handle = std::thread::Builder::new()
+            .name("traceon-writer".to_string())
+            .spawn(move || work(&worker, writer))
+            .expect("failed to spawn the traceon writer thread");
+
+        let guard = WriterGuard {
+            shared: shared.clone(),
+            handle: Some(handle),
+        };
+        (NonBlocking { shared }, guard)
+    }
+
+    /// Total number of records dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queue a record, applying the `QueueFull` policy if there's no room
+    pub(crate) fn push(&self, record: Vec<u8>) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.records.len() >= self.shared.capacity {
+            match self.shared.queue_full {
+                QueueFull::Block => {
+                    while state.records.len() >= self.shared.capacity && !state.closed {
+                        state = self.shared.not_full.wait(state).unwrap();
+                    }
+                }
+                QueueFull::DropNewest => {
+                    self.shared.count_dropped();
+                    return;
+                }
+                QueueFull::DropOldest => {
+                    state.records.pop_front();
+                    self.shared.count_dropped();
+                }
+            }
+        }
+        // Records logged after the guard is dropped have nowhere to go
+        if state.closed {
+            return;
+        }
+        state.records.push_back(record);
+        self.shared.not_empty.notify_one();
+    }
+
+    /// If records have been dropped since the last report and there's room in the queue, queue a record with the
+    /// number of records dropped. The first call sets the `Reporter` that builds it, which also reports any records
+    /// dropped after the last report when the guard is dropped.
+    pub(crate) fn report_dropped(&self, reporter: impl FnOnce() -> Reporter) {
+        if !self.shared.has_reporter.load(Ordering::Relaxed) {
+            let mut shared_reporter = self.shared.reporter.lock().unwrap();
+            if !self.shared.has_reporter.swap(true, Ordering::Relaxed) {
+                *shared_reporter = Some(reporter());
+            }
+        }
+        self.shared.report_dropped(false);
+    }
+}
+
+impl WriterGuard {
+    /// Total number of records dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for WriterGuard {
+    fn drop(&mut self) {
+        self.shared.report_dropped(true);
+        // The reporter holds a layer that holds this writer, taking it lets them both be freed
+        let reporter = self.shared.reporter.lock().unwrap().take();
+        drop(reporter);
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.not_empty.notify_all();
+        self.shared.not_full.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Shared {
+    fn count_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.unreported.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Queue a record with the number of records dropped since the last report if there's room, or even if the
+    /// queue is full when `shutdown` is set. The record is built without holding the lock on the queue.
+    fn report_dropped(&self, shutdown: bool) {
+        if self.unreported.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let Some(reporter) = self.reporter.lock().unwrap().clone() else {
+            return;
+        };
+        let unreported = self.unreported.swap(0, Ordering::Relaxed);
+        if unreported == 0 {
+            return;
+        }
+        let Some(record) = reporter(unreported) else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.closed || (!shutdown && state.records.len() >= self.capacity) {
+            // Reported with the next record that finds room in the queue
+            self.unreported.fetch_add(unreported, Ordering::Relaxed);
+            return;
+        }
+        state.records.push_back(record);
+        self.not_empty.notify_one();
+    }
+}
+
+/// Drain the queue in batches until the guard is dropped, flushing every time the queue is empty
+fn work(shared: &Shared, mut writer: impl Write) {
+    loop {
+        let batch: Vec<Vec<u8>> = {
+            let mut state = shared.state.lock().unwrap();
+            while state.records.is_empty() && !state.closed {
+                state = shared.not_empty.wait(state).unwrap();
+            }
+            if state.records.is_empty() {
+                break;
+            }
+            shared.not_full.notify_all();
+            state.records.drain(..).collect()
+        };
+        for record in batch {
+            if let Err(e) = writer.write_all(&record) {
+                eprintln!("traceon writer thread failed to write: {e}");
+            }
+        }
+        if let Err(e) = writer.flush() {
+            eprintln!("traceon writer thread failed to flush: {e}");
+        }
+    }
+}
+
+/// Callsite for the record written when a `NonBlocking` writer has dropped records
+struct DroppedCallsite;
+
+static DROPPED_CALLSITE: DroppedCallsite = DroppedCallsite;
+
+pub(crate) static DROPPED_METADATA: Metadata<'static> = Metadata::new(
+    "dropped records",
+    "traceon",
+    Level::WARN,
+    Some(file!()),
+    Some(line!()),
+    Some(module_path!()),
+    FieldSet::new(&[], Identifier(&DROPPED_CALLSITE)),
+    Kind::EVENT,
+);
+
+impl Callsite for DroppedCallsite {
+    fn set_interest(&self, _: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        &DROPPED_METADATA
+    }
+}
@@ -0,0 +1,38 @@
+use opentelemetry::trace::{SamplingDecision, TraceContextExt, TraceFlags};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// Trace id, span id, and trace flags if the sampling decision is known yet
+pub(crate) struct OtelIds {
+    pub trace_id: String,
+    pub span_id: String,
+    pub trace_flags: Option<String>,
+}
+
+/// Read the OpenTelemetry ids that `tracing-opentelemetry` stores in the span's extensions
+pub(crate) fn otel_ids<S>(span: &SpanRef<'_, S>) -> Option<OtelIds>
+where
+    S: for<'a> LookupSpan<'a>,
+{
+    let extensions = span.extensions();
+    let data = extensions.get::<OtelData>()?;
+    let parent = data.parent_cx.span();
+    let parent = parent.span_context();
+    // Only root spans have a trace id on the builder, children inherit it from the parent context
+    let trace_id = data.builder.trace_id.unwrap_or_else(|| parent.trace_id());
+    let span_id = data.builder.span_id?;
+    // The sampling decision is made lazily, either when a child span starts or the span closes
+    let trace_flags = match &data.builder.sampling_result {
+        Some(result) => Some(match result.decision {
+            SamplingDecision::RecordAndSample => TraceFlags::SAMPLED,
+            SamplingDecision::Drop | SamplingDecision::RecordOnly => TraceFlags::default(),
+        }),
+        None if data.parent_cx.has_active_span() => Some(parent.trace_flags()),
+        None => None,
+    };
+    Some(OtelIds {
+        trace_id: trace_id.to_string(),
+        span_id: span_id.to_string(),
+        trace_flags: trace_flags.map(|flags| format!("{flags:02x}")),
+    })
+}
@@ -0,0 +1,267 @@
+use crate::color::{Theme, paint};
+use nu_ansi_term::Style;
+use serde_json::Value;
+use std::borrow::Cow;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Values are only wrapped if there's at least this many columns left for them after the keys
+const MIN_WRAP_WIDTH: usize = 20;
+
+/// When to wrap long values in pretty output, set with `wrap()` on the builder
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Wrap {
+    /// Wrap at the width of the terminal when writing to stdout or stderr and it's a terminal
+    #[default]
+    Terminal,
+    /// Wrap at this many columns for every writer
+    Width(usize),
+    /// Never wrap, the terminal decides where long lines break
+    None,
+}
+
+/// How a record is laid out and colored for the writer it's going to
+pub(crate) struct Layout<'a> {
+    /// The theme if colors are on
+    pub theme: Option<&'a Theme>,
+    /// Columns to wrap pretty values at
+    pub width: Option<usize>,
+    /// Show strings that contain a json map or sequence as a sub-tree
+    pub json_strings: bool,
+}
+
+impl Layout<'_> {
+    fn style(&self, style: impl FnOnce(&Theme) -> Style) -> Option<Style> {
+        self.theme.map(style)
+    }
+
+    /// Parse a string that looks like a json map or sequence if `json_strings` is on
+    fn expand<'v>(&self, value: &'v Value) -> Cow<'v, Value> {
+        if self.json_strings
+            && let Value::String(string) = value
+            && matches!(string.trim_start().as_bytes().first(), Some(b'{' | b'['))
+            && let Ok(parsed) = serde_json::from_str::<Value>(string)
+            && is_tree(&parsed)
+        {
+            return Cow::Owned(parsed);
+        }
+        Cow::Borrowed(value)
+    }
+}
+
+/// A value as text without quotes or escapes
+pub(crate) fn text(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::String(string) => Cow::Borrowed(string),
+        value => Cow::Owned(value.to_string()),
+    }
+}
+
+fn is_tree(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => false,
+    }
+}
+
+/// Label of a line in a tree of values
+#[derive(Clone, Copy)]
+pub(crate) enum Label<'a> {
+    /// A key of a map, followed by a colon
+    Key(&'a str),
+    /// An item of a sequence, a dash
+    Item,
+}
+
+impl Label<'_> {
+    fn width(&self) -> usize {
+        match self {
+            Label::Key(key) => key.width() + 1,
+            Label::Item => 1,
+        }
+    }
+}
+
+/// A recorded error with its message and the messages from its chain of sources
+fn is_error(value: &Value) -> bool {
+    value.get("message").is_some_and(Value::is_string)
+        && value.get("sources").is_some_and(Value::is_array)
+}
+
+/**
+Write each entry on its own line at `indent` with the label e.g. `key:` or `-` for sequence items. Values that fit on
+a line are aligned after the widest label and wrapped under themselves, maps and sequences become an indented
+sub-tree below their label, and errors have their message on the line followed by an indented "caused by" list of
+their sources, and the spans they were created in if there's a span trace.
+*/
+pub(crate) fn write_entries(
+    out: &mut String,
+    entries: &[(Label<'_>, &Value)],
+    indent: usize,
+    layout: &Layout,
+) {
+    let values: Vec<Cow<Value>> = entries
+        .iter()
+        .map(|(_, value)| layout.expand(value))
+        .collect();
+    let is_block = |value: &Value| is_tree(value) && !is_error(value);
+    let label_width = entries
+        .iter()
+        .zip(&values)
+        .filter(|(_, value)| !is_block(value))
+        .map(|((label, _), _)| label.width())
+        .max()
+        .unwrap_or(0);
+    let column = indent + label_width + 1;
+
+    for ((label, _), value) in entries.iter().zip(&values) {
+        out.push('\n');
+        push_spaces(out, indent);
+        let key_style = layout.style(|theme| theme.key);
+        match label {
+            Label::Key(key) => {
+                paint(out, key_style, key);
+                out.push(':');
+            }
+            Label::Item => paint(out, key_style, "-"),
+        }
+        if is_error(value) {
+            push_spaces(out, column - indent - label.width());
+            write_error(out, value, column, indent, layout);
+        } else if is_block(value) {
+            let children: Vec<(Label, &Value)> = match value.as_ref() {
+                Value::Object(map) => map
+                    .iter()
+                    .map(|(key, value)| (Label::Key(key), value))
+                    .collect(),
+                Value::Array(items) => items.iter().map(|value| (Label::Item, value)).collect(),
+                _ => Vec::new(),
+            };
+            write_entries(out, &children, indent + 4, layout);
+        } else {
+            push_spaces(out, column - indent - label.width());
+            let style = layout.style(|theme| theme.value(value));
+            write_text(out, &text(value), style, column, layout.width);
+        }
+    }
+}
+
+fn write_error(out: &mut String, error: &Value, column: usize, indent: usize, layout: &Layout) {
+    let mut message = text(&error["message"]).into_owned();
+    if let Some(name) = error.get("type").and_then(Value::as_str) {
+        message.push_str(&format!(" ({name})"));
+    }
+    write_text(
+        out,
+        &message,
+        layout.style(|theme| theme.string),
+        column,
+        layout.width,
+    );
+
+    let sources = error["sources"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    if !sources.is_empty() {
+        out.push('\n');
+        push_spaces(out, indent + 4);
+        out.push_str("caused by:");
+        let items: Vec<(Label, &Value)> =
+            sources.iter().map(|source| (Label::Item, source)).collect();
+        write_entries(out, &items, indent + 8, layout);
+    }
+    if let Some(spans) = error.get("span_trace").and_then(Value::as_array) {
+        let spans: Vec<Value> = spans
+            .iter()
+            .map(|span| {
+                let mut line = span
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                if let Some(fields) = span.get("fields").and_then(Value::as_str) {
+                    line.push_str(&format!(" {{{fields}}}"));
+                }
+                if let Some(location) = span.get("location").and_then(Value::as_str) {
+                    line.push_str(&format!(" at {location}"));
+                }
+                Value::from(line)
+            })
+            .collect();
+        out.push('\n');
+        push_spaces(out, indent + 4);
+        out.push_str("span trace:");
+        let items: Vec<(Label, &Value)> = spans.iter().map(|span| (Label::Item, span)).collect();
+        write_entries(out, &items, indent + 8, layout);
+    }
+}
+
+/// Write text starting at `column`, each of its lines and any lines wrapped to fit `width` continue below it at the
+/// same column, and are painted separately so the escape codes don't run into the indent
+pub(crate) fn write_text(
+    out: &mut String,
+    text: &str,
+    style: Option<Style>,
+    column: usize,
+    width: Option<usize>,
+) {
+    let available = width
+        .map(|width| width.saturating_sub(column))
+        .filter(|available| *available >= MIN_WRAP_WIDTH);
+    let mut first = true;
+    let mut push_line = |line: &str| {
+        if !first {
+            out.push('\n');
+            push_spaces(out, column);
+        }
+        paint(out, style, line);
+        first = false;
+    };
+    for line in text.split('\n').map(|line| line.trim_end_matches('\r')) {
+        match available {
+            Some(available) => wrap(line, available).into_iter().for_each(&mut push_line),
+            None => push_line(line),
+        }
+    }
+}
+
+/// Split a line into pieces no wider than `width` columns, breaking at the last space that fits where possible
+fn wrap(line: &str, width: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while rest.width() > width {
+        let mut used = 0;
+        let mut end = 0;
+        let mut last_space = None;
+        for (i, ch) in rest.char_indices() {
+            used += ch.width().unwrap_or(0);
+            if used > width {
+                // The line can break at a space right after the last character that fits
+                if ch == ' ' {
+                    last_space = Some(i);
+                }
+                break;
+            }
+            end = i + ch.len_utf8();
+            if ch == ' ' && i > 0 {
+                last_space = Some(i);
+            }
+        }
+        // A character wider than the whole width still has to go somewhere
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let split = last_space.unwrap_or(end);
+        pieces.push(&rest[..split]);
+        rest = rest[split..].trim_start_matches(' ');
+    }
+    if !rest.is_empty() || pieces.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+fn push_spaces(out: &mut String, count: usize) {
+    out.extend(std::iter::repeat_n(' ', count));
+}
@@ -0,0 +1,88 @@
+use serde_json::Value;
+
+/// What to do with the value of a field that matches a redaction pattern
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Redact {
+    #[default]
+    /// Replace the value with `[REDACTED]`
+    Mask,
+    /// Remove the field completely
+    Remove,
+    /// Keep the last number of characters and mask the rest e.g. `KeepLast(4)` turns `4111111111111111` into `****1111`
+    KeepLast(usize),
+}
+
+/// Field name patterns and what to do with the values of matching fields
+#[derive(Clone, Debug)]
+pub(crate) struct Redaction {
+    patterns: Vec<String>,
+    redact: Redact,
+}
+
+impl Redaction {
+    pub fn new(patterns: &[&str], redact: Redact) -> Redaction {
+        Redaction {
+            patterns: patterns
+                .iter()
+                .map(|pattern| pattern.to_ascii_lowercase())
+                .collect(),
+            redact,
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| glob(pattern.as_bytes(), name.as_bytes()))
+    }
+}
+
+/// Apply the first redaction that matches `name`, returning `None` if the field should be removed
+pub(crate) fn redact(redactions: &[Redaction], name: &str, value: Value) -> Option<Value> {
+    let Some(redaction) = redactions.iter().find(|redaction| redaction.matches(name)) else {
+        return Some(value);
+    };
+    match redaction.redact {
+        Redact::Mask => Some(Value::from("[REDACTED]")),
+        Redact::Remove => None,
+        Redact::KeepLast(count) => {
+            let value = match value {
+                Value::String(string) => string,
+                value => value.to_string(),
+            };
+            let chars = value.chars().count();
+            // Don't reveal anything if the value isn't longer than what would be kept
+            let kept: String = if chars > count {
+                value.chars().skip(chars - count).collect()
+            } else {
+                String::new()
+            };
+            Some(Value::from(format!("****{kept}")))
+        }
+    }
+}
+
+/// Case insensitive match where `*` matches any number of characters and `?` matches one character, the pattern
+/// must already be lowercase
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` in the pattern, and the position in the name it was matched from
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n].to_ascii_lowercase()) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last `*` match one more character and try again
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == b'*')
+}
@@ -0,0 +1,121 @@
+use crate::filter::{FilterConfig, FilterError};
+use crate::traceon::Traceon;
+use std::fmt;
+use tracing::Level;
+use tracing_subscriber::{EnvFilter, Registry, layer::Layered, reload};
+
+pub(crate) type FormatHandle = reload::Handle<Traceon, Registry>;
+pub(crate) type FilterHandle =
+    reload::Handle<EnvFilter, Layered<reload::Layer<Traceon, Registry>, Registry>>;
+
+/**
+Returned from `on()` and `try_on()` to change the filter and formatting while the program is running. Changes are
+made under a lock, so a record that is being formatted when a change is made is written entirely with the old
+settings, and the next one entirely with the new settings:
+```
+let handle = traceon::builder().try_on()?;
+traceon::debug!("filtered out at the default info level");
+
+// Turn on debug logs and switch to json without a restart
+handle.filter("debug")?;
+handle.modify(|traceon| traceon.json().file())?;
+traceon::debug!("written as json");
+# Ok::<(), Box<dyn std::error::Error>>(())
+```
+*/
+#[derive(Clone)]
+pub struct ReloadHandle {
+    format: FormatHandle,
+    filter: FilterHandle,
+}
+
+impl ReloadHandle {
+    pub(crate) fn new(format: FormatHandle, filter: FilterHandle) -> ReloadHandle {
+        ReloadHandle { format, filter }
+    }
+
+    /// Replace the filter with new directives e.g. `info,my_crate=debug`, the environment isn't read
+    ///
+    /// # Errors
+    /// Returns an error if the directives are invalid, or the subscriber has been dropped
+    pub fn filter(&self, directives: &str) -> Result<(), ReloadError> {
+        self.reload_filter(FilterConfig {
+            directives: directives.to_string(),
+            targets: Vec::new(),
+            env_var: None,
+        })
+    }
+
+    /// Replace the filter with new directives and typed levels per target, the environment isn't read
+    ///
+    /// # Errors
+    /// Returns an error if the directives are invalid, or the subscriber has been dropped
+    pub fn filter_with_targets(
+        &self,
+        directives: &str,
+        targets: &[(&str, Level)],
+    ) -> Result<(), ReloadError> {
+        self.reload_filter(FilterConfig {
+            directives: directives.to_string(),
+            targets: targets
+                .iter()
+                .map(|(target, level)| (target.to_string(), *level))
+                .collect(),
+            env_var: None,
+        })
+    }
+
+    /// Change the formatting options with the same methods as the builder e.g. `|traceon| traceon.json().file()`. Don't log from inside `modify`, the
+    /// formatting layer is locked until it returns.
+    ///
+    /// # Errors
+    /// Returns an error if the subscriber has been dropped
+    pub fn modify(
+        &self,
+        modify: impl FnOnce(&mut Traceon) -> &mut Traceon,
+    ) -> Result<(), ReloadError> {
+        self.format
+            .modify(|traceon| {
+                modify(traceon);
+            })
+            .map_err(ReloadError::Reload)
+    }
+
+    fn reload_filter(&self, config: FilterConfig) -> Result<(), ReloadError> {
+        let filter = config.build().map_err(ReloadError::Filter)?;
+        self.filter.reload(filter).map_err(ReloadError::Reload)
+    }
+}
+
+impl fmt::Debug for ReloadHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadHandle").finish_non_exhaustive()
+    }
+}
+
+/// Error returned from `ReloadHandle`
+#[derive(Debug)]
+pub enum ReloadError {
+    /// The new filter directives couldn't be parsed
+    Filter(FilterError),
+    /// The subscriber has been dropped, or a thread panicked while changing it
+    Reload(reload::Error),
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Filter(error) => error.fmt(f),
+            ReloadError::Reload(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ReloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReloadError::Filter(error) => Some(error),
+            ReloadError::Reload(error) => Some(error),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// How often a `RollingFile` rotates, based on UTC time
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    /// Only rotate when the file reaches its max size
+    Never,
+    /// Rotate at the start of every minute
+    Minutely,
+    /// Rotate at the start of every hour
+    Hourly,
+    /// Rotate at the start of every day
+    Daily,
+}
+
+impl Rotation {
+    fn period(self) -> Option<TimeDelta> {
+        match self {
+            Rotation::Never => None,
+            Rotation::Minutely => Some(TimeDelta::minutes(1)),
+            Rotation::Hourly => Some(TimeDelta::hours(1)),
+            Rotation::Daily => Some(TimeDelta::days(1)),
+        }
+    }
+
+    /// The next time the file should rotate after `now`
+    fn next(self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let period = self.period()?;
+        let start = now.duration_trunc(period).unwrap_or(now);
+        Some(start + period)
+    }
+}
+
+/**
+Settings for a log file that rotates on size and/or time, and cleans up old files. Records are always written to
+`directory/file_name`, when it rotates the file is renamed with the UTC time as a suffix e.g.
+`app.log.2023-01-01-12-00-00`, and a new file is started.
+
+```no_run
+use traceon::{RollingFile, Rotation};
+use std::time::Duration;
+
+let file = RollingFile::new("logs", "app.log")
+    // Rotate when the file would go over 10 MiB
+    .max_size(10 * 1024 * 1024)
+    // And at the start of every day
+    .rotation(Rotation::Daily)
+    // Keep the 5 most recently rotated files
+    .max_files(5)
+    // Delete rotated files older than a week
+    .max_age(Duration::from_secs(7 * 24 * 60 * 60));
+
+traceon::builder().json().rolling_file(file)?.on();
+# Ok::<(), std::io::Error>(())
+```
+*/
+#[derive(Clone, Debug)]
+pub struct RollingFile {
+    directory: PathBuf,
+    file_name: String,
+    rotation: Rotation,
+    max_size: Option<u64>,
+    max_files: Option<usize>,
+    max_age: Option<Duration>,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+}
+
+/// Writer returned from `RollingFile::open`, rotates the file as records are written
+#[derive(Debug)]
+pub struct RollingFileWriter {
+    settings: RollingFile,
+    file: File,
+    size: u64,
+    next_rotation: Option<DateTime<Utc>>,
+}
+
+impl RollingFile {
+    /// Write to `file_name` in `directory`, which is created if it doesn't exist
+    pub fn new(directory: impl AsRef<Path>, file_name: impl Into<String>) -> RollingFile {
+        RollingFile {
+            directory: directory.as_ref().to_path_buf(),
+            file_name: file_name.into(),
+            rotation: Rotation::Never,
+            max_size: None,
+            max_files: None,
+            max_age: None,
+            #[cfg(feature = "gzip")]
+            gzip: false,
+        }
+    }
+
+    /// Rotate the file before a record would take it over `bytes`, a single record larger than this still gets a file
+    /// to itself
+    #[must_use]
+    pub fn max_size(mut self, bytes: u64) -> RollingFile {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotate the file on a schedule
+    #[must_use]
+    pub fn rotation(mut self, rotation: Rotation) -> RollingFile {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Only keep the `count` most recently rotated files, the file currently being written to isn't counted
+    #[must_use]
+    pub fn max_files(mut self, count: usize) -> RollingFile {
+        self.max_files = Some(count);
+        self
+    }
+
+    /// Delete rotated files that were last written to longer than `age` ago
+    #[must_use]
+    pub fn max_age(mut self, age: Duration) -> RollingFile {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Compress rotated files with gzip, adding a `.gz` extension. Compression happens on the thread that triggers
+    /// the rotation, combine with a `NonBlocking` writer so it doesn't hold up logging.
+    #[cfg(feature = "gzip")]
+    #[must_use]
+    pub fn gzip(mut self) -> RollingFile {
+        self.gzip = true;
+        self
+    }
+
+    /// Create the directory if required, and open the file for appending
+    ///
+    /// # Errors
+    /// Returns an error if the directory or file can't be created
+    pub fn open(self) -> io::Result<RollingFileWriter> {
+        fs::create_dir_all(&self.directory)?;
+        let file = open_append(&self.path())?;
+        let size = file.metadata()?.len();
+        Ok(RollingFileWriter {
+            next_rotation: self.rotation.next(Utc::now()),
+            settings: self,
+            file,
+            size,
+        })
+    }
+
+    fn path(&self) -> PathBuf {
+        self.directory.join(&self.file_name)
+    }
+
+    /// Rotated files start with the file name followed by a `.`
+    fn is_rotated(&self, name: &str) -> bool {
+        name.len() > self.file_name.len() + 1
+            && name.starts_with(&self.file_name)
+            && name.as_bytes()[self.file_name.len()] == b'.'
+    }
+}
+
+impl RollingFileWriter {
+    fn should_rotate(&self, len: usize) -> bool {
+        if let Some(next_rotation) = self.next_rotation
+            && Utc::now() >= next_rotation
+        {
+            return true;
+        }
+        match self.settings.max_size {
+            Some(max_size) => self.size > 0 && self.size + len as u64 > max_size,
+            None => false,
+        }
+    }
+
+    /// Rename the current file, start a new one, and clean up old files. Only failing to start the new file is an
+    /// error, failures to compress or remove rotated files are printed to stderr.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let now = Utc::now();
+        let path = self.settings.path();
+        let suffix = now.format("%Y-%m-%d-%H-%M-%S").to_string();
+        let mut rotated = self
+            .settings
+            .directory
+            .join(format!("{}.{suffix}", self.settings.file_name));
+        let mut count = 1;
+        while rotated.exists() || gz_path(&rotated).exists() {
+            rotated = self
+                .settings
+                .directory
+                .join(format!("{}.{suffix}.{count}", self.settings.file_name));
+            count += 1;
+        }
+        fs::rename(&path, &rotated)?;
+        self.file = open_append(&path)?;
+        self.size = 0;
+        self.next_rotation = self.settings.rotation.next(now);
+
+        // The record still goes to the new file if cleaning up the rotated files fails, e.g. the disk is full
+        #[cfg(feature = "gzip")]
+        if self.settings.gzip
+            && let Err(e) = gzip(&rotated)
+        {
+            eprintln!(
+                "traceon failed to compress rotated file {}: {e}",
+                rotated.display()
+            );
+        }
+        if let Err(e) = self.prune() {
+            eprintln!(
+                "traceon failed to remove old rotated files in {}: {e}",
+                self.settings.directory.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Delete rotated files past `max_files` or `max_age`
+    fn prune(&self) -> io::Result<()> {
+        if self.settings.max_files.is_none() && self.settings.max_age.is_none() {
+            return Ok(());
+        }
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(&self.settings.directory)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if !self.settings.is_rotated(&name.to_string_lossy()) {
+                continue;
+            }
+            let modified = entry.metadata()?.modified()?;
+            rotated.push((modified, entry.path()));
+        }
+        // Newest first
+        rotated.sort_by(|a, b| b.cmp(a));
+
+        let now = SystemTime::now();
+        for (index, (modified, path)) in rotated.into_iter().enumerate() {
+            let too_many = self.settings.max_files.is_some_and(|max| index >= max);
+            let too_old = self
+                .settings
+                .max_age
+                .is_some_and(|max| now.duration_since(modified).is_ok_and(|age| age > max));
+            if too_many || too_old {
+                // Another writer for the same file may have already removed it
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for RollingFileWriter {
+    /// Each call writes the whole buffer to one file, so records aren't split between files
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".gz");
+    path.into()
+}
+
+/// Compress a file to `<path>.gz` and remove the original
+#[cfg(feature = "gzip")]
+fn gzip(path: &Path) -> io::Result<()> {
+    let mut input = File::open(path)?;
+    let output = File::create(gz_path(path))?;
+    let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)
+}
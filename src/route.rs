@@ -0,0 +1,116 @@
+use crate::Format;
+use crate::rolling::RollingFile;
+use crate::{NonBlocking, traceon::Writer};
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+use tracing::{Level, Metadata};
+
+/**
+A destination for events that match a level threshold and/or target prefixes, add it to the builder with `route()`.
+A route with no level or targets matches every event.
+
+```
+use traceon::{Format, Level, Route};
+
+// Errors from anywhere, written as json
+let errors = Route::stderr().level(Level::ERROR).format(Format::Json);
+
+// Warnings and errors from the `hyper` and `h2` crates
+let dependencies = Route::writer(std::io::sink())
+    .level(Level::WARN)
+    .target("hyper")
+    .target("h2");
+
+traceon::builder().route(errors).route(dependencies).on();
+```
+*/
+#[derive(Clone)]
+pub struct Route {
+    level: Option<Level>,
+    targets: Vec<String>,
+    pub(crate) format: Option<Format>,
+    pub(crate) writer: Writer,
+}
+
+impl Route {
+    /// Route to any writer that is threadsafe and implements the `Write` trait
+    pub fn writer(writer: impl Write + Send + Sync + 'static) -> Route {
+        Route::new(Writer::Locked(Arc::new(Mutex::new(writer))))
+    }
+
+    /// Route to stdout, colors are turned on automatically when stdout is a terminal
+    pub fn stdout() -> Route {
+        Route::new(Writer::stdout())
+    }
+
+    /// Route to stderr, colors are turned on automatically when stderr is a terminal
+    pub fn stderr() -> Route {
+        Route::new(Writer::stderr())
+    }
+
+    /// Route to a buffer that you can share between threads by wrapping it in an Arc and Mutex
+    pub fn buffer(buffer: Arc<Mutex<impl Write + Send + Sync + 'static>>) -> Route {
+        Route::new(Writer::Locked(buffer))
+    }
+
+    /// Route to a `NonBlocking` writer
+    pub fn non_blocking(writer: NonBlocking) -> Route {
+        Route::new(Writer::NonBlocking(writer))
+    }
+
+    /// Route to a `RollingFile`
+    ///
+    /// # Errors
+    /// Returns an error if the directory or file can't be created
+    pub fn rolling_file(file: RollingFile) -> std::io::Result<Route> {
+        Ok(Route::writer(file.open()?))
+    }
+
+    fn new(writer: Writer) -> Route {
+        Route {
+            level: None,
+            targets: Vec::new(),
+            format: None,
+            writer,
+        }
+    }
+
+    /// Only match events at this level or more severe e.g. `Level::WARN` matches warnings and errors
+    #[must_use]
+    pub fn level(mut self, level: Level) -> Route {
+        self.level = Some(level);
+        self
+    }
+
+    /// Only match events with this target or a child module of it e.g. `hyper` matches `hyper` and
+    /// `hyper::client`, call it again to match more targets
+    #[must_use]
+    pub fn target(mut self, target: impl Into<String>) -> Route {
+        self.targets.push(target.into());
+        self
+    }
+
+    /// Use a different format for this route, the builder's format is used otherwise
+    #[must_use]
+    pub fn format(mut self, format: Format) -> Route {
+        self.format = Some(format);
+        self
+    }
+
+    pub(crate) fn matches(&self, metadata: &Metadata<'_>) -> bool {
+        if let Some(level) = self.level
+            && *metadata.level() > level
+        {
+            return false;
+        }
+        self.targets.is_empty()
+            || self.targets.iter().any(|target| {
+                metadata
+                    .target()
+                    .strip_prefix(target.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+    }
+}
@@ -0,0 +1,158 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt::{self, Debug, Display},
+};
+
+thread_local! {
+    /// Set to `Some(None)` while traceon is recording a field, so a `Structured` value can hand over its json
+    static CAPTURE: RefCell<Option<Option<Value>>> = const { RefCell::new(None) };
+}
+
+/**
+Wrap a value that implements `serde::Serialize` so maps, sequences and structs are recorded as nested json instead of
+a `Debug` string, and render as indented sub-trees in pretty output. Record it with `?` on events or spans:
+```
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct User {
+    name: &'static str,
+    roles: Vec<&'static str>,
+}
+
+traceon::builder().json().on();
+let user = User { name: "jack", roles: vec!["admin", "dev"] };
+traceon::info!(user = ?traceon::structured(&user), sizes = ?traceon::structured([10, 15, 20]), "logged in");
+```
+
+output:
+```json
+{"time":"2023-01-01T12:58:49.448Z","level":"INFO","message":"logged in","user":{"name":"jack","roles":["admin","dev"]},"sizes":[10,15,20]}
+```
+
+Other layers see the value formatted as a json string.
+*/
+pub fn structured<T: Serialize>(value: T) -> Structured<T> {
+    Structured(value)
+}
+
+/// A value recorded as nested json, created with `traceon::structured()`
+#[derive(Clone, Copy)]
+pub struct Structured<T>(T);
+
+impl<T: Serialize> Debug for Structured<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let captured = CAPTURE.with_borrow_mut(|capture| match capture {
+            Some(slot @ None) => match serde_json::to_value(&self.0) {
+                Ok(value) => {
+                    *slot = Some(value);
+                    true
+                }
+                Err(_) => false,
+            },
+            _ => false,
+        });
+        if captured {
+            return Ok(());
+        }
+        match serde_json::to_string(&self.0) {
+            Ok(json) => f.write_str(&json),
+            Err(e) => write!(f, "<failed to serialize: {e}>"),
+        }
+    }
+}
+
+impl<T: Serialize> Display for Structured<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+/// Convert a `Debug` field to json, a `Structured` value keeps its structure and anything else becomes a string,
+/// formatted with `{:#?}` if `alternate` is set
+pub(crate) fn debug_value(value: &dyn Debug, alternate: bool) -> Value {
+    let previous = CAPTURE.with_borrow_mut(|capture| capture.replace(None));
+    let formatted = if alternate {
+        format!("{value:#?}")
+    } else {
+        format!("{value:?}")
+    };
+    let captured = CAPTURE.with_borrow_mut(|capture| std::mem::replace(capture, previous));
+    match captured {
+        Some(Some(value)) if formatted.is_empty() => value,
+        // Nested inside another type's `Debug` output, so format again with the json as a string
+        Some(Some(_)) if alternate => Value::from(format!("{value:#?}")),
+        Some(Some(_)) => Value::from(format!("{value:?}")),
+        _ => Value::from(formatted),
+    }
+}
+
+/// Convert an error to json with its message, the name of its type if it can be found, the messages from its chain
+/// of sources, and the `SpanTrace` from `tracing-error` if one is attached and the feature is on
+pub(crate) fn error_value(error: &(dyn Error + 'static)) -> Value {
+    let mut map = serde_json::Map::new();
+    map.insert("message".to_string(), Value::from(error.to_string()));
+    if let Some(name) = type_name(error) {
+        map.insert("type".to_string(), Value::from(name));
+    }
+    let sources: Vec<Value> = std::iter::successors(error.source(), |&source| source.source())
+        .map(|source| Value::from(source.to_string()))
+        .collect();
+    map.insert("sources".to_string(), Value::from(sources));
+    #[cfg(feature = "tracing-error")]
+    if let Some(span_trace) = span_trace(error) {
+        map.insert("span_trace".to_string(), span_trace);
+    }
+    Value::from(map)
+}
+
+/// `dyn Error` doesn't expose the type name, so take it from the start of the `Debug` output which is the name of
+/// the struct for derived implementations
+fn type_name(error: &(dyn Error + 'static)) -> Option<String> {
+    // Its `Debug` output starts with the name of the internal representation instead
+    if error.is::<std::io::Error>() {
+        return Some("std::io::Error".to_string());
+    }
+    let debug = format!("{error:?}");
+    let end = debug
+        .find(|ch: char| !ch.is_alphanumeric() && ch != '_' && ch != ':')
+        .unwrap_or(debug.len());
+    let (name, rest) = debug.split_at(end);
+    let is_type = name.starts_with(char::is_uppercase)
+        && (rest.is_empty() || rest.starts_with(" {") || rest.starts_with('('));
+    is_type.then(|| name.to_string())
+}
+
+/// The spans from the first error in the chain created with `tracing_error::InstrumentError`, from the innermost
+/// span outwards
+#[cfg(feature = "tracing-error")]
+fn span_trace(error: &(dyn Error + 'static)) -> Option<Value> {
+    use tracing_error::{ExtractSpanTrace, SpanTraceStatus};
+
+    let span_trace = std::iter::successors(Some(error), |&error| error.source())
+        .find_map(|error| error.span_trace())
+        .filter(|span_trace| span_trace.status() == SpanTraceStatus::CAPTURED)?;
+    let mut spans = Vec::new();
+    span_trace.with_spans(|metadata, fields| {
+        let mut span = serde_json::Map::new();
+        span.insert(
+            "name".to_string(),
+            Value::from(format!("{}::{}", metadata.target(), metadata.name())),
+        );
+        if !fields.is_empty() {
+            span.insert("fields".to_string(), Value::from(fields));
+        }
+        if let (Some(file), Some(line)) = (metadata.file(), metadata.line()) {
+            span.insert(
+                "location".to_string(),
+                Value::from(format!("{file}:{line}")),
+            );
+        }
+        spans.push(Value::from(span));
+        true
+    });
+    Some(Value::from(spans))
+}
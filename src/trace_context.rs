@@ -0,0 +1,190 @@
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    time::SystemTime,
+};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::{Registry, registry::LookupSpan};
+
+/// Ids stored in the span's extensions when trace ids are on
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub parent_span_id: Option<u64>,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// A new span in the same trace as `parent`, or a new trace if there's no parent
+    pub fn new(parent: Option<TraceParent>) -> TraceContext {
+        match parent {
+            Some(parent) => TraceContext {
+                trace_id: parent.trace_id,
+                span_id: random_u64(),
+                parent_span_id: Some(parent.span_id),
+                sampled: parent.sampled,
+            },
+            None => TraceContext {
+                trace_id: (u128::from(random_u64()) << 64) | u128::from(random_u64()),
+                span_id: random_u64(),
+                parent_span_id: None,
+                sampled: true,
+            },
+        }
+    }
+
+    pub fn traceparent(&self) -> TraceParent {
+        TraceParent {
+            trace_id: self.trace_id,
+            span_id: self.span_id,
+            sampled: self.sampled,
+        }
+    }
+}
+
+/**
+A W3C trace context `traceparent` e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`, used to continue a
+trace from another service or process when trace ids are turned on with `trace_ids()`.
+
+A root span continues the trace from a `traceparent` field if it has one, e.g. from an incoming request header:
+```
+traceon::builder().json().trace_ids().on();
+
+let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+let _span = traceon::info_span!("request", traceparent = header).entered();
+traceon::info!("trace_id is 4bf92f3577b34da6a3ce929d0e0e4736 and parent_span_id is 00f067aa0ba902b7");
+```
+
+Otherwise it continues from the `TRACEPARENT` environment variable if it was set when `trace_ids()` was called, so
+pass the current context to child processes with:
+```
+use traceon::TraceParent;
+
+let mut command = std::process::Command::new("echo");
+if let Some(traceparent) = TraceParent::current() {
+    command.env("TRACEPARENT", traceparent.to_string());
+}
+```
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceParent {
+    /// 128-bit id shared by every span in the trace
+    pub trace_id: u128,
+    /// 64-bit id of the parent span
+    pub span_id: u64,
+    /// If the trace is being recorded by the caller
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    /// Parse a `traceparent` string, returning `None` if it isn't valid
+    pub fn parse(value: &str) -> Option<TraceParent> {
+        let mut parts = value.trim().split('-');
+        let version = parts.next().filter(|version| is_hex(version, 2))?;
+        let trace_id = parts.next().filter(|trace_id| is_hex(trace_id, 32))?;
+        let span_id = parts.next().filter(|span_id| is_hex(span_id, 16))?;
+        let flags = parts.next().filter(|flags| is_hex(flags, 2))?;
+        // Later versions can add more parts, but version 00 has exactly four and ff is never valid
+        if version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let span_id = u64::from_str_radix(span_id, 16).ok()?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if trace_id == 0 || span_id == 0 {
+            return None;
+        }
+        Some(TraceParent {
+            trace_id,
+            span_id,
+            sampled: flags & 1 == 1,
+        })
+    }
+
+    /// The context of the current span, `None` if there's no current span or trace ids aren't on
+    pub fn current() -> Option<TraceParent> {
+        tracing::dispatcher::get_default(|dispatch| {
+            let registry = dispatch.downcast_ref::<Registry>()?;
+            let current = dispatch.current_span();
+            let span = registry.span(current.id()?)?;
+            let extensions = span.extensions();
+            extensions
+                .get::<TraceContext>()
+                .map(TraceContext::traceparent)
+        })
+    }
+
+    /// Parse the `TRACEPARENT` environment variable, `None` if it isn't set or isn't valid
+    pub fn from_env() -> Option<TraceParent> {
+        TraceParent::parse(&std::env::var("TRACEPARENT").ok()?)
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            u8::from(self.sampled)
+        )
+    }
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+/// Reads a `traceparent` field from the attributes of a new root span
+#[derive(Default)]
+pub(crate) struct TraceParentVisitor(pub Option<TraceParent>);
+
+impl Visit for TraceParentVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "traceparent" {
+            self.0 = TraceParent::parse(value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "traceparent" {
+            self.0 = TraceParent::parse(format!("{value:?}").trim_matches('"'));
+        }
+    }
+}
+
+/// Random non-zero id from a splitmix64 generator per thread, seeded from the std hasher's random keys
+fn random_u64() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(seed());
+    }
+    loop {
+        let next = STATE.with(|state| {
+            let next = state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+            state.set(next);
+            next
+        });
+        let mut z = next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        if z != 0 {
+            return z;
+        }
+    }
+}
+
+fn seed() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish()
+}
@@ -1,30 +1,48 @@
-use nu_ansi_term::{Color, Style};
+use crate::color::{ColorMode, Theme, env_colors, paint, paint_json};
+use crate::dedup::{Dedup, DedupState, Suppressed};
+use crate::ecs;
+use crate::env::{EnvConfig, EnvError};
+use crate::filter::{FilterConfig, OnError};
+use crate::google_cloud::{self, SOURCE_LOCATION_KEY};
+use crate::json::{Json, Object};
+use crate::non_blocking::{DROPPED_METADATA, NonBlocking};
+use crate::pretty::{Label, Layout, Wrap, text, write_entries, write_text};
+use crate::redact::{Redact, Redaction, redact};
+use crate::reload::ReloadHandle;
+use crate::rolling::RollingFile;
+use crate::route::Route;
+use crate::structured::{debug_value, error_value};
+use crate::trace_context::{TraceContext, TraceParent, TraceParentVisitor};
 // use erased_serde::{Serialize, Serializer};
 use chrono::offset::TimeZone as TimeZoneTrait;
 use chrono::{DateTime, Local, SecondsFormat, Utc};
 use serde::ser::{SerializeMap, Serializer};
 use serde_json::Value;
 use std::{
-    collections::HashMap,
-    io::Write,
-    sync::{Arc, Mutex},
+    borrow::Cow,
+    cell::Cell,
+    io::{IsTerminal, Write},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
 };
 use tracing::Level;
 use tracing::{
-    Event, Id, Subscriber,
+    Event, Id, Metadata, Subscriber,
     field::{Field, Visit},
     span::Attributes,
 };
 use tracing_subscriber::{
-    EnvFilter, Layer, Registry,
+    Layer, Registry,
     layer::{Context, SubscriberExt},
+    registry::SpanRef,
+    reload,
 };
 
 /// Private struct to initialize formatting and storage layers
 /// All members can be modified through public methods.
 #[derive(Clone)]
 pub struct Traceon {
-    json: bool,
+    format: Format,
     file: bool,
     module: bool,
     span_format: SpanFormat,
@@ -33,8 +51,27 @@ pub struct Traceon {
     timezone: TimeZone,
     join_fields: JoinFields,
     level: LevelFormat,
-    writer: Arc<Mutex<dyn Write + Sync + Send>>,
-    message_key: &'static str,
+    span_events: SpanEvents,
+    writer: Writer,
+    routes: Vec<Route>,
+    redactions: Arc<Vec<Redaction>>,
+    keys: Keys,
+    trace_keys: TraceKeys,
+    google_project: Option<Arc<str>>,
+    trace_ids: bool,
+    env_traceparent: Option<TraceParent>,
+    filter: FilterConfig,
+    dedup: Option<DedupState>,
+    default_fields: Arc<Vec<(String, Value)>>,
+    field_order: Arc<Vec<String>>,
+    case_exempt: Arc<Vec<String>>,
+    color: ColorMode,
+    env_colors: Option<bool>,
+    colored_json: bool,
+    theme: Theme,
+    wrap: Wrap,
+    json_strings: bool,
+    alternate_debug: bool,
 }
 
 /// Change case of keys
@@ -48,6 +85,32 @@ pub enum Case {
     Pascal,
     /// Convert all keys to snake_case
     Snake,
+    /// Convert all keys to SCREAMING_SNAKE_CASE
+    ScreamingSnake,
+    /// Convert all keys to kebab-case
+    Kebab,
+    /// Convert all keys to dot.case
+    Dot,
+}
+
+/// Output format of each record
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    /// Message on a colored headline with the fields indented on new lines below it
+    Pretty,
+    /// One line of flattened json per record
+    Json,
+    /// One line of flattened `key=value` pairs per record
+    Logfmt,
+    /// One line of json per record, with the event fields under `fields` and a `spans` array from the root to the
+    /// current span, where each span has its name and only its own fields
+    NestedJson,
+    /// One line of json per record in the structured format Google Cloud Logging reads natively, use
+    /// `google_cloud()` to also link records to Cloud Trace
+    GoogleCloud,
+    /// One line of json per record in the Elastic Common Schema, with the fields nested in their namespaces
+    Ecs,
 }
 
 /// Format the log level
@@ -79,6 +142,86 @@ pub enum SpanFormat {
     Overwrite,
 }
 
+/**
+Keys for the built-in fields in every format, converted to the `Case` setting like any other key. In pretty output the
+time, level and message are on the headline without their keys.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Keys {
+    /// Key for the time the record was written
+    pub time: &'static str,
+    /// Key for the level of the event
+    pub level: &'static str,
+    /// Key for the message of the event
+    pub message: &'static str,
+    /// Key for the module path of the callsite, only written by `module()`
+    pub module: &'static str,
+    /// Key for the file and line of the callsite, only written by `file()`
+    pub file: &'static str,
+    /// Key for the name of the span, or the joined names of the span and its parents
+    pub span: &'static str,
+    /// Key for the event fields in nested json
+    pub fields: &'static str,
+    /// Key for the array of spans in nested json
+    pub spans: &'static str,
+    /// Key for the name of each span in the nested json array of spans
+    pub name: &'static str,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Keys {
+            time: "time",
+            level: "level",
+            message: "message",
+            module: "module",
+            file: "file",
+            span: "span",
+            fields: "fields",
+            spans: "spans",
+            name: "name",
+        }
+    }
+}
+
+/// Keys for the trace context fields, converted to the `Case` setting like any other key
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceKeys {
+    /// Key for the 128-bit trace id as 32 hex characters
+    pub trace_id: &'static str,
+    /// Key for the 64-bit span id as 16 hex characters
+    pub span_id: &'static str,
+    /// Key for the 64-bit id of the parent span as 16 hex characters, only written by `trace_ids()`
+    pub parent_span_id: &'static str,
+    /// Key for the W3C trace flags as 2 hex characters e.g. `01` when sampled, only written for OpenTelemetry
+    pub trace_flags: &'static str,
+}
+
+impl Default for TraceKeys {
+    fn default() -> Self {
+        TraceKeys {
+            trace_id: "trace_id",
+            span_id: "span_id",
+            parent_span_id: "parent_span_id",
+            trace_flags: "trace_flags",
+        }
+    }
+}
+
+/// Write records for the lifecycle of a span
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SpanEvents {
+    #[default]
+    /// Don't write any records for span lifecycles
+    None,
+    /// Write a record when a span closes with the elapsed, busy and idle time
+    Close,
+    /// Write a record when a span is created, and when it closes with the elapsed, busy and idle time
+    NewAndClose,
+    /// Write a record when a span is created, entered, exited, and when it closes with the elapsed, busy and idle time
+    Full,
+}
+
 impl Default for SpanFormat {
     fn default() -> Self {
         SpanFormat::Join("::")
@@ -133,13 +276,47 @@ pub enum TimeZone {
     Local,
 }
 
-/// Convert json values with \n and \" characters to their escaped values when in pretty mode
-pub fn clean_json_value(value: &Value) -> String {
-    value
-        .to_string()
-        .trim_matches('"')
-        .replace("\\\"", "\"")
-        .replace("\\n", "\n    ")
+/// Write a `key=value` pair to a logfmt line, quoting and escaping the value if required
+fn logfmt_entry(
+    buffer: &mut Vec<u8>,
+    key: &str,
+    value: &Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for ch in key.chars() {
+        if ch.is_whitespace() || ch == '=' || ch == '"' {
+            buffer.push(b'_');
+        } else {
+            buffer.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+    buffer.push(b'=');
+
+    let value = match value {
+        Value::Null => Cow::Borrowed(""),
+        value => text(value),
+    };
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|ch| ch.is_whitespace() || ch.is_control() || ch == '=' || ch == '"');
+    if !needs_quotes {
+        buffer.extend_from_slice(value.as_bytes());
+        return Ok(());
+    }
+    buffer.push(b'"');
+    for ch in value.chars() {
+        match ch {
+            '"' => buffer.extend_from_slice(b"\\\""),
+            '\\' => buffer.extend_from_slice(b"\\\\"),
+            '\n' => buffer.extend_from_slice(b"\\n"),
+            '\r' => buffer.extend_from_slice(b"\\r"),
+            '\t' => buffer.extend_from_slice(b"\\t"),
+            ch if ch.is_control() => write!(buffer, "\\u{:04x}", ch as u32)?,
+            ch => buffer.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    buffer.push(b'"');
+    Ok(())
 }
 
 /// Convert a datetime to String based on the TimeFormat
@@ -168,17 +345,36 @@ where
 impl Default for Traceon {
     fn default() -> Traceon {
         Traceon {
-            json: false,
+            format: Format::Pretty,
             file: false,
             module: false,
-            message_key: "message",
+            keys: Keys::default(),
+            trace_keys: TraceKeys::default(),
+            google_project: None,
+            trace_ids: false,
+            env_traceparent: None,
+            filter: FilterConfig::default(),
+            dedup: None,
+            default_fields: Arc::default(),
+            field_order: Arc::default(),
+            case_exempt: Arc::default(),
+            color: ColorMode::Auto,
+            env_colors: env_colors(),
+            colored_json: false,
+            theme: Theme::default(),
+            wrap: Wrap::Terminal,
+            json_strings: false,
+            alternate_debug: false,
             span_format: SpanFormat::Join("::"),
             case: Case::None,
             time: TimeFormat::RFC3339,
             timezone: TimeZone::UTC,
             join_fields: JoinFields::Overwrite,
             level: crate::LevelFormat::Uppercase,
-            writer: Arc::new(Mutex::new(std::io::stdout())),
+            span_events: SpanEvents::None,
+            writer: Writer::stdout(),
+            routes: Vec::new(),
+            redactions: Arc::default(),
         }
     }
 }
@@ -236,7 +432,43 @@ impl Traceon {
         self
     }
 
-    /// Change the key for the message field when using the json formatter
+    /**
+    Write records when spans start and end, the end record has the time in milliseconds since the span was created,
+    how long it was entered for, and how long it was idle in between:
+    ```
+    use traceon::SpanEvents;
+    traceon::builder().span_events(SpanEvents::NewAndClose).on();
+
+    let span = tracing::info_span!("request", id = 5);
+    span.in_scope(|| tracing::info!("handling request"));
+    ```
+
+    pretty output:
+    ```text
+    12:02:43 INFO request [START]
+        span: request
+        id:   5
+
+    12:02:43 INFO handling request
+        span: request
+        id:   5
+
+    12:02:43 INFO request [END]
+        elapsed_milliseconds: 0.102
+        busy_milliseconds:    0.041
+        idle_milliseconds:    0.061
+        span:                 request
+        id:                   5
+    ```
+    */
+    #[must_use]
+    pub fn span_events(&mut self, span_events: SpanEvents) -> &mut Self {
+        self.span_events = span_events;
+        self
+    }
+
+    /// Change the key for the message field when using the json or logfmt formatter, `keys()` renames the other
+    /// built-in fields
     /// ```
     /// traceon::builder().json().message_key("msg").on();
     /// traceon::info!("the message key is now msg");
@@ -250,7 +482,90 @@ impl Traceon {
     /// ```
     #[must_use]
     pub fn message_key(&mut self, message_key: &'static str) -> &mut Self {
-        self.message_key = message_key;
+        self.keys.message = message_key;
+        self
+    }
+
+    /**
+    Change the keys for the built-in fields, e.g. to match what a log platform expects:
+    ```
+    use traceon::Keys;
+
+    traceon::builder()
+        .json()
+        .module()
+        .keys(Keys {
+            time: "@timestamp",
+            level: "severity",
+            module: "logger",
+            ..Keys::default()
+        })
+        .on();
+
+    traceon::info!("renamed");
+    ```
+
+    json output:
+    ```json
+    {"@timestamp":"2023-01-01T12:58:49.448Z","severity":"INFO","message":"renamed","logger":"my_crate"}
+    ```
+    */
+    #[must_use]
+    pub fn keys(&mut self, keys: Keys) -> &mut Self {
+        self.keys = keys;
+        self
+    }
+
+    /**
+    Give each root span a random 128-bit trace id, and each span a random 64-bit span id, then write `trace_id`,
+    `span_id` and `parent_span_id` on every record in a span. Use `TraceParent` to continue a trace from another
+    service or process, the `TRACEPARENT` environment variable is read when this is called:
+    ```
+    traceon::builder().json().trace_ids().on();
+
+    let _span = traceon::info_span!("request").entered();
+    let _span = traceon::info_span!("query").entered();
+    traceon::info!("correlated");
+    ```
+
+    json output:
+    ```json
+    {
+        "message": "correlated",
+        "trace_id": "4bf92f3577b34da6a3ce929d0e0e4736",
+        "span_id": "a3ce929d0e0e4736",
+        "parent_span_id": "00f067aa0ba902b7",
+        "span": "request::query"
+    }
+    ```
+    */
+    #[must_use]
+    pub fn trace_ids(&mut self) -> &mut Self {
+        self.trace_ids = true;
+        self.env_traceparent = TraceParent::from_env();
+        self
+    }
+
+    /**
+    Change the keys for the trace context fields from `trace_ids()`, or with the `opentelemetry` feature on and a
+    `tracing_opentelemetry` layer in the subscriber, the OpenTelemetry trace id, span id, and trace flags once the
+    sampling decision has been made:
+    ```
+    use traceon::TraceKeys;
+
+    traceon::builder()
+        .json()
+        .trace_keys(TraceKeys {
+            trace_id: "traceId",
+            span_id: "spanId",
+            ..TraceKeys::default()
+        })
+        .on();
+    ```
+    */
+    #[must_use]
+    pub fn trace_keys(&mut self, trace_keys: TraceKeys) -> &mut Self {
+        self.trace_keys = trace_keys;
         self
     }
 
@@ -286,9 +601,9 @@ impl Traceon {
     pretty output:
     ```text
     12:44:12 INFO testing field join
+        span:    span_1::span_1
         field_a: changed
         field_b: original||changed
-        span:    span_1::span_1
     ```
     */
     #[must_use]
@@ -317,274 +632,1401 @@ impl Traceon {
     /// Use json formatting instead of pretty formatting
     #[must_use]
     pub fn json(&mut self) -> &mut Self {
-        self.json = true;
+        self.format = Format::Json;
+        self
+    }
+    /**
+    Use logfmt formatting instead of pretty formatting, values containing spaces, quotes, `=` or control characters
+    are quoted and escaped:
+    ```
+    traceon::builder().logfmt().message_key("msg").on();
+    traceon::info!(user = "jack clayton", "logged in");
+    ```
+
+    output:
+    ```text
+    time=2023-01-01T12:58:49.448Z level=INFO msg="logged in" user="jack clayton"
+    ```
+    */
+    #[must_use]
+    pub fn logfmt(&mut self) -> &mut Self {
+        self.format = Format::Logfmt;
+        self
+    }
+    /**
+    Use json formatting that keeps the span hierarchy instead of flattening it, the event fields go under `fields`
+    and `spans` is an array from the root to the current span, where each span has its name and only its own fields:
+    ```
+    traceon::builder().nested_json().on();
+
+    let _span = traceon::info_span!("request", id = 5).entered();
+    let _span = traceon::info_span!("query", id = 10).entered();
+    traceon::info!(rows = 2, "query complete");
+    ```
+
+    output prettified:
+    ```json
+    {
+      "time": "2023-01-01T12:58:49.448Z",
+      "level": "INFO",
+      "message": "query complete",
+      "fields": { "rows": 2 },
+      "spans": [
+        { "name": "request", "id": 5 },
+        { "name": "query", "id": 10 }
+      ]
+    }
+    ```
+    */
+    #[must_use]
+    pub fn nested_json(&mut self) -> &mut Self {
+        self.format = Format::NestedJson;
+        self
+    }
+    /**
+    Write one line of json per record in the structured format Google Cloud Logging reads natively, e.g. from stdout
+    on GKE or Cloud Run. The level is written as the `severity`, the file, line and module path go in
+    `logging.googleapis.com/sourceLocation`, and records in a span with trace ids from `trace_ids()` or OpenTelemetry
+    are linked to the trace in `project_id`:
+    ```
+    traceon::builder().google_cloud("my-project").trace_ids().on();
+
+    let _span = traceon::info_span!("request").entered();
+    traceon::warn!(user = "jack", "slow request");
+    ```
+
+    output prettified:
+    ```json
+    {
+      "time": "2023-01-01T12:58:49.448123456Z",
+      "severity": "WARNING",
+      "message": "slow request",
+      "logging.googleapis.com/sourceLocation": { "file": "src/main.rs", "line": "5", "function": "my_crate" },
+      "logging.googleapis.com/trace": "projects/my-project/traces/4bf92f3577b34da6a3ce929d0e0e4736",
+      "logging.googleapis.com/spanId": "00f067aa0ba902b7",
+      "user": "jack",
+      "span": "request"
+    }
+    ```
+
+    The time is always RFC 3339 in UTC unless it's turned off with `TimeFormat::None`, and the `keys()` and
+    `Case` settings only apply to the other fields.
+    */
+    #[must_use]
+    pub fn google_cloud(&mut self, project_id: &str) -> &mut Self {
+        self.format = Format::GoogleCloud;
+        self.google_project = Some(Arc::from(project_id));
+        self
+    }
+    /**
+    Write one line of json per record in the Elastic Common Schema, so Elasticsearch can index it without a
+    pipeline to rewrite the fields. The level, module path, file and line go under `log`, the joined span name is
+    `span.name`, the trace ids from `trace_ids()` or OpenTelemetry are `trace.id` and `span.id`, and the other
+    fields go under `labels`. Fields with a dotted key or a map value keep their own namespace instead, so they can
+    fill in other ECS fields:
+    ```
+    traceon::builder().ecs().on();
+
+    let _span = traceon::info_span!("request").entered();
+    traceon::info!(http.request.method = "GET", user = "jack", "handled request");
+    ```
+
+    output prettified:
+    ```json
+    {
+      "@timestamp": "2023-01-01T12:58:49.448Z",
+      "log": {
+        "level": "info",
+        "logger": "my_crate",
+        "origin": { "file": { "name": "src/main.rs", "line": 5 } }
+      },
+      "message": "handled request",
+      "ecs": { "version": "8.11.0" },
+      "http": { "request": { "method": "GET" } },
+      "labels": { "user": "jack" },
+      "span": { "name": "request" }
+    }
+    ```
+
+    The time is always RFC 3339 in UTC unless it's turned off with `TimeFormat::None`, and the `keys()` and
+    `Case` settings only apply to the other fields.
+    */
+    #[must_use]
+    pub fn ecs(&mut self) -> &mut Self {
+        self.format = Format::Ecs;
+        self
+    }
+    /// Change the output format, `json()`, `nested_json()` and `logfmt()` are shortcuts for this
+    #[must_use]
+    pub fn format(&mut self, format: Format) -> &mut Self {
+        self.format = format;
         self
     }
     /// Use any writer that is threadsafe and implements the `Write` trait
     #[must_use]
     pub fn writer(&mut self, writer: impl Write + Send + Sync + 'static) -> &mut Self {
-        self.writer = Arc::new(Mutex::new(writer));
+        self.writer = Writer::Locked(Arc::new(Mutex::new(writer)));
         self
     }
-    /// Write to a buffer that you can share between threads by wrapping it in an Arc and Mutex
+    /// Write to stdout, which is the default. Unlike `writer(std::io::stdout())`, colors are turned on automatically
+    /// when stdout is a terminal.
     #[must_use]
-    pub fn buffer(&mut self, buffer: Arc<Mutex<impl Write + Send + Sync + 'static>>) -> &mut Self {
-        self.writer = buffer;
+    pub fn stdout(&mut self) -> &mut Self {
+        self.writer = Writer::stdout();
         self
     }
-    /// Change casing of keys to match a specefic format
+    /// Write to stderr, colors are turned on automatically when stderr is a terminal
     #[must_use]
-    pub fn case(&mut self, case: Case) -> &mut Self {
-        self.case = case;
+    pub fn stderr(&mut self) -> &mut Self {
+        self.writer = Writer::stderr();
         self
     }
-
-    /// Turn on the storage, formatting and filter layers as a global default, which means all threads will inherit it but it can
-    /// be overwritten for a single thread with for example: `let _guard = traceon::builder().on_thread();`
+    /// Write to a buffer that you can share between threads by wrapping it in an Arc and Mutex
+    #[must_use]
+    pub fn buffer(&mut self, buffer: Arc<Mutex<impl Write + Send + Sync + 'static>>) -> &mut Self {
+        self.writer = Writer::Locked(buffer);
+        self
+    }
+    /// Write to a file that rotates on size and/or time, see `RollingFile` for the options
+    /// ```no_run
+    /// use traceon::{RollingFile, Rotation};
     ///
-    /// # Panics
+    /// traceon::builder()
+    ///     .json()
+    ///     .rolling_file(RollingFile::new("logs", "app.log").rotation(Rotation::Hourly).max_files(24))?
+    ///     .on();
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
     ///
-    /// Will panic if the global default subscriber is already set, use `try_on` instead to return a `Result`
-    pub fn on(&self) {
-        let env_filter =
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-        let subscriber = Registry::default().with(self.clone()).with(env_filter);
-
-        // Panic if user is trying to set two global default subscribers
-        tracing::subscriber::set_global_default(subscriber)
-            .expect("more than one global default subscriber set");
+    /// # Errors
+    /// Returns an error if the directory or file can't be created
+    pub fn rolling_file(&mut self, file: RollingFile) -> std::io::Result<&mut Self> {
+        self.writer = Writer::Locked(Arc::new(Mutex::new(file.open()?)));
+        Ok(self)
     }
-
-    /// Turn on the storage, formatting and filter layers as a global default, which means all threads will inherit it but it can
-    /// be overwritten for a single thread with for example: `let _guard = traceon::builder().on_thread();`
+    /// Write through a `NonBlocking` writer, which queues records for a dedicated writer thread instead of writing
+    /// on the thread that is logging. When records are dropped because the queue is full, a warning with the
+    /// `dropped_count` is written as soon as there's room in the queue again.
+    /// ```
+    /// use traceon::{NonBlocking, QueueFull};
     ///
-    /// Returns a result which will be an error if the global default subscriber is already set
-    pub fn try_on(&self) -> Result<(), tracing::subscriber::SetGlobalDefaultError> {
-        let env_filter =
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-        let subscriber = Registry::default().with(self.clone()).with(env_filter);
-
-        tracing::subscriber::set_global_default(subscriber)
+    /// let (writer, guard) = NonBlocking::new(std::io::stderr(), 1024, QueueFull::DropNewest);
+    /// traceon::builder().non_blocking(writer).on();
+    /// traceon::info!("queued for the writer thread");
+    ///
+    /// // Dropping the guard flushes the queue and stops the writer thread
+    /// drop(guard);
+    /// ```
+    #[must_use]
+    pub fn non_blocking(&mut self, writer: NonBlocking) -> &mut Self {
+        self.writer = Writer::NonBlocking(writer);
+        self
     }
-
     /**
-    Turn on the storage, formatting and filter layers on the local thread returning a guard, when the guard is dropped the
-    layers will be unsubscribed.
-
-    # Examples
-
+    Send events to a different writer and format based on their level and target, routes are checked in the order
+    they're added and the first that matches takes the event, events that don't match any route go to the default
+    writer:
     ```
-    // Turn on the subscriber with json formatting
-    let _span = tracing::info_span!("the storage layer in this subscriber will have a field", field = "temp");
-    let _guard = traceon::builder().json().on_thread();
-    tracing::info!("first subscriber");
+    use traceon::{Format, Level, Route};
 
+    traceon::builder()
+        // Warnings and errors go to stderr
+        .route(Route::stderr().level(Level::WARN))
+        // Everything from a noisy dependency goes to a sink as json
+        .route(Route::writer(std::io::sink()).target("hyper").format(Format::Json))
+        // Everything else goes to stdout
+        .stdout()
+        .on();
 
-    // Drop the previous subscriber and storage for the fields, this new one has pretty formatting
-    let _span = tracing::info!("the storage layer has been reset");
-    let _guard = traceon::builder().on_thread();
-    tracing::info!("second subscriber")
+    traceon::warn!("written to stderr");
+    traceon::info!(target: "hyper::client", "written to the sink");
+    traceon::info!("written to stdout");
     ```
-
-    Returns a result which will be an error if the global default subscriber is already set
     */
-    pub fn on_thread(&self) -> tracing::subscriber::DefaultGuard {
-        let env_filter =
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-        let subscriber = Registry::default().with(self.clone()).with(env_filter);
+    #[must_use]
+    pub fn route(&mut self, route: Route) -> &mut Self {
+        self.routes.push(route);
+        self
+    }
+    /**
+    Redact the values of fields with names matching any of the `patterns` before they reach any output, this
+    includes `Debug` fields and fields inherited from parent spans. Patterns are case insensitive, `*` matches
+    any number of characters and `?` matches a single character. Call it again for different patterns, the first
+    matching redaction is applied:
+    ```
+    use traceon::Redact;
 
-        tracing::subscriber::set_default(subscriber)
+    #[traceon::instrument]
+    fn login(user: &str, password: &str, card_number: u64) {
+        traceon::info!(session_token = "abc123", "logged in");
     }
 
-    /// Serialize a single event
-    fn serialize<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
-        &self,
-        event: &Event<'_>,
-        ctx: Context<'_, S>,
-        event_visitor: &mut JsonStorage,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let mut msg = Vec::new();
-        let mut pretty_buffer = Vec::new();
-        let mut json_buffer = Vec::new();
-
-        let mut serializer = serde_json::Serializer::new(&mut json_buffer);
-        let mut map_serializer = serializer.serialize_map(None)?;
-        let current_span = ctx.lookup_current();
-        event.record(event_visitor);
-
-        let (level_key, file_key, module_key, timestamp_key) = match self.case {
-            Case::Pascal => ("Level", "File", "Module", "Time"),
-            _ => ("level", "file", "module", "time"),
-        };
+    traceon::builder()
+        .redact(&["password", "authorization"], Redact::Mask)
+        .redact(&["*token*"], Redact::Remove)
+        .redact(&["card_number"], Redact::KeepLast(4))
+        .on();
 
-        let metadata = event.metadata();
+    login("jack", "hunter2", 4111111111111111);
+    ```
 
-        if self.time != TimeFormat::None {
-            let time_string = match self.timezone {
-                TimeZone::UTC => {
-                    let now = Utc::now();
-                    time_convert(now, &self.time)
-                }
-                TimeZone::Local => {
-                    let now = Local::now();
-                    time_convert(now, &self.time)
-                }
-            };
-            if self.json {
-                map_serializer.serialize_entry(timestamp_key, &time_string)?;
-            } else {
-                write!(msg, "{time_string} ")?;
-            }
-        }
-        match self.level {
-            LevelFormat::Uppercase => {
-                if self.json {
-                    map_serializer.serialize_entry(level_key, &metadata.level().to_string())?;
-                } else {
-                    write!(msg, "{} ", metadata.level())?;
-                }
-            }
-            LevelFormat::Lowercase => {
-                if self.json {
-                    map_serializer.serialize_entry(
-                        level_key,
-                        &metadata.level().to_string().to_ascii_lowercase(),
-                    )?;
-                } else {
-                    write!(
-                        msg,
-                        "{} ",
-                        metadata.level().to_string().to_ascii_lowercase()
-                    )?;
-                }
-            }
-            LevelFormat::Number => {
-                let number = match *metadata.level() {
-                    Level::TRACE => 10,
-                    Level::DEBUG => 20,
-                    Level::INFO => 30,
-                    Level::WARN => 40,
-                    Level::ERROR => 50,
-                };
+    pretty output:
+    ```text
+    12:30:02 INFO logged in
+        span:        login
+        user:        jack
+        password:    [REDACTED]
+        card_number: ****1111
+    ```
+    */
+    #[must_use]
+    pub fn redact(&mut self, patterns: &[&str], redact: Redact) -> &mut Self {
+        Arc::make_mut(&mut self.redactions).push(Redaction::new(patterns, redact));
+        self
+    }
+    /**
+    Add constant fields to every record, such as the service name or version. Keys follow the `Case` setting, and
+    event or span fields with the same key take priority over a default field:
+    ```
+    use serde_json::json;
 
-                if self.json {
-                    map_serializer.serialize_entry(level_key, &number)?;
-                } else {
-                    write!(msg, "{} ", number)?;
-                }
+    traceon::builder()
+        .json()
+        .default_fields([
+            ("service", json!("checkout")),
+            ("version", json!(env!("CARGO_PKG_VERSION"))),
+            ("region", json!({ "name": "us-east-1", "zone": "b" })),
+        ])
+        .on();
+    ```
+    */
+    #[must_use]
+    pub fn default_fields<K: Into<String>, V: Into<Value>>(
+        &mut self,
+        fields: impl IntoIterator<Item = (K, V)>,
+    ) -> &mut Self {
+        let mut default_fields = self.default_fields.to_vec();
+        for (key, value) in fields {
+            let key = key.into();
+            let value = value.into();
+            match default_fields
+                .iter_mut()
+                .find(|(existing, _)| *existing == key)
+            {
+                Some((_, existing)) => *existing = value,
+                None => default_fields.push((key, value)),
             }
-            LevelFormat::None => (),
         }
+        self.default_fields = Arc::new(default_fields);
+        self
+    }
 
-        if !self.json {
-            let style = match *event.metadata().level() {
-                Level::TRACE => Style::new().fg(Color::Purple),
-                Level::DEBUG => Style::new().fg(Color::Blue),
-                Level::INFO => Style::new().fg(Color::Green),
-                Level::WARN => Style::new().fg(Color::Yellow),
-                Level::ERROR => Style::new().fg(Color::Red),
-            };
-
-            if let Some(value) = event_visitor.values.get("message") {
-                let message = clean_json_value(value);
-                write!(msg, "{message}")?;
-            } else {
-                write!(msg, "event triggered")?;
-            };
-            let msg = String::from_utf8_lossy(&msg);
-            let msg = msg.trim();
-            writeln!(pretty_buffer, "{}", style.paint(msg))?;
-        }
+    /**
+    Pin keys to the front of every record in the order given, in all formats. Keys can be the field name as it's
+    recorded or as it's written after the `Case` setting. Fields are otherwise written with the built-in keys first,
+    then the event fields in the order they're declared, then the span fields from the root to the current span, and
+    the default fields last:
+    ```
+    traceon::builder().json().field_order(&["request_id", "user"]).on();
 
-        let mut fields = Vec::new();
+    let _span = traceon::info_span!("request", request_id = "abc").entered();
+    traceon::info!(user = "jack", "logged in");
+    ```
 
-        if self.module {
-            if self.json {
-                map_serializer
-                    .serialize_entry(module_key, metadata.module_path().unwrap_or_default())?;
-            } else {
-                fields.push((
-                    module_key.to_string(),
-                    metadata.module_path().unwrap_or_default().to_string(),
-                ));
-            }
-        }
+    output:
+    ```json
+    {"request_id":"abc","user":"jack","time":"2023-01-01T12:58:49.448Z","level":"INFO","message":"logged in","span":"request"}
+    ```
 
-        if self.file {
-            let value = format!(
-                "{}:{}",
-                metadata.file().unwrap_or_default(),
-                metadata.line().unwrap_or_default()
-            );
+    In pretty output the time, level and message stay on the headline, and pinned keys are the first fields below it.
+    */
+    #[must_use]
+    pub fn field_order(&mut self, keys: &[&str]) -> &mut Self {
+        self.field_order = Arc::new(keys.iter().map(ToString::to_string).collect());
+        self
+    }
 
-            if self.json {
-                map_serializer.serialize_entry(file_key, &value)?;
-            } else {
-                fields.push((file_key.to_string(), value.to_string()));
-            }
+    /// Stop repeated events flooding the output, see `Dedup` for how repeats are found and counted. When it's changed
+    /// on a `ReloadHandle`, the repeats from the windows that are still open are written first.
+    #[must_use]
+    pub fn dedup(&mut self, dedup: Dedup) -> &mut Self {
+        if let Some(previous) = self.dedup.replace(DedupState::new(dedup)) {
+            self.record_suppressed::<Registry>(previous.pending());
         }
+        self
+    }
+    /**
+    Choose when the output is colored, the default `ColorMode::Auto` colors stdout and stderr when they're a terminal
+    and nothing else. Setting `NO_COLOR` turns colors off, and setting `FORCE_COLOR` turns them on for every writer:
+    ```
+    use traceon::ColorMode;
+
+    // Keep colors when piping to `less -R`
+    traceon::builder().color(ColorMode::Always).on();
+    ```
+    */
+    #[must_use]
+    pub fn color(&mut self, color: ColorMode) -> &mut Self {
+        self.color = color;
+        self
+    }
+    /// Change the colors used for levels, keys and values when the output is colored, see `Theme`
+    #[must_use]
+    pub fn theme(&mut self, theme: Theme) -> &mut Self {
+        self.theme = theme;
+        self
+    }
+    /**
+    Color json output like jq when colors are on, so it's easier to read in a terminal. It's off by default, as
+    colored json is no longer valid json for anything reading the output:
+    ```
+    traceon::builder().json().colored_json().on();
+    ```
+    */
+    #[must_use]
+    pub fn colored_json(&mut self) -> &mut Self {
+        self.colored_json = true;
+        self
+    }
+    /**
+    Choose when long values in pretty output are wrapped, the default `Wrap::Terminal` wraps at the terminal width
+    when writing to a terminal. Wrapped lines and the lines of multi-line values continue under the start of the
+    value:
+    ```
+    use traceon::Wrap;
+
+    traceon::builder().wrap(Wrap::Width(60)).on();
+    traceon::info!(query = "SELECT id, name, email FROM users WHERE created_at > now() - interval '1 day'", "slow");
+    ```
+
+    output:
+    ```text
+    2023-01-01T12:58:49.448Z INFO slow
+        query: SELECT id, name, email FROM users WHERE
+               created_at > now() - interval '1 day'
+    ```
+    */
+    #[must_use]
+    pub fn wrap(&mut self, wrap: Wrap) -> &mut Self {
+        self.wrap = wrap;
+        self
+    }
+    /**
+    Show string fields that contain a json map or sequence as a sub-tree in pretty output, e.g. a request body that
+    was already serialized:
+    ```
+    traceon::builder().pretty_json_strings().on();
+    traceon::info!(body = r#"{"user":"jack","roles":["admin"]}"#, "request");
+    ```
+
+    output:
+    ```text
+    2023-01-01T12:58:49.448Z INFO request
+        body:
+            user: jack
+            roles:
+                - admin
+    ```
+    */
+    #[must_use]
+    pub fn pretty_json_strings(&mut self) -> &mut Self {
+        self.json_strings = true;
+        self
+    }
+    /// Write `Debug` fields with the alternate `{:#?}` format in pretty output, so structs and collections are spread
+    /// over multiple lines that are indented under their key. Other formats, including routes to them, keep `{:?}` so
+    /// each record stays on one line.
+    #[must_use]
+    pub fn alternate_debug(&mut self) -> &mut Self {
+        self.alternate_debug = true;
+        self
+    }
+    /// Change casing of keys to match a specefic format
+    #[must_use]
+    pub fn case(&mut self, case: Case) -> &mut Self {
+        self.case = case;
+        self
+    }
+
+    /**
+    Keep keys as they're recorded instead of converting them to the `Case` setting, e.g. HTTP header names:
+    ```
+    use traceon::Case;
+
+    traceon::builder().json().case(Case::Snake).case_exempt(&["X-Request-Id"]).on();
+    traceon::info!("X-Request-Id" = "abc", userName = "jack", "request");
+    ```
+
+    output:
+    ```json
+    {"time":"2023-01-01T12:58:49.448Z","level":"INFO","message":"request","X-Request-Id":"abc","user_name":"jack"}
+    ```
+    */
+    #[must_use]
+    pub fn case_exempt(&mut self, keys: &[&str]) -> &mut Self {
+        self.case_exempt = Arc::new(keys.iter().map(ToString::to_string).collect());
+        self
+    }
+
+    /**
+    Override the current settings with any `TRACEON_*` environment variables that are set, so a deployed binary can
+    be reconfigured without a rebuild. Settings from the environment overwrite anything set on the builder before
+    calling this, and anything set after calling this overwrites the environment:
+    ```
+    // TRACEON_FORMAT can switch this back to pretty, but the module field will always be on
+    traceon::builder().json().env()?.module().on();
+    # Ok::<(), traceon::EnvError>(())
+    ```
 
-        // Add all the fields from the current event.
-        for (mut key, value) in event_visitor.values.iter() {
-            if self.json && key == &"message" {
-                key = &self.message_key;
+    See [`crate::from_env`] for the variables and their values.
+
+    # Errors
+    Returns an `EnvError` naming the variable and the valid values if any variable is set to an invalid value
+    */
+    pub fn env(&mut self) -> Result<&mut Self, EnvError> {
+        let config = EnvConfig::read()?;
+        if let Some(format) = config.format {
+            self.format = format;
+        }
+        if let Some(project_id) = config.google_project {
+            self.google_project = Some(project_id);
+        }
+        if let Some(file) = config.file {
+            self.file = file;
+        }
+        if let Some(module) = config.module {
+            self.module = module;
+        }
+        if let Some(span_format) = config.span_format {
+            self.span_format = span_format;
+        }
+        if let Some(span_events) = config.span_events {
+            self.span_events = span_events;
+        }
+        if let Some(case) = config.case {
+            self.case = case;
+        }
+        if let Some(time) = config.time {
+            self.time = time;
+        }
+        if let Some(timezone) = config.timezone {
+            self.timezone = timezone;
+        }
+        if let Some(level) = config.level {
+            self.level = level;
+        }
+        if let Some(message_key) = config.message_key {
+            self.keys.message = message_key;
+        }
+        if let Some(color) = config.color {
+            self.color = color;
+        }
+        Ok(self)
+    }
+
+    /**
+    Change the filter directives used when the environment variable isn't set, the default is `info`. Uses the same
+    syntax as `RUST_LOG` e.g. `info,my_crate=debug,hyper=warn`:
+    ```
+    traceon::builder().filter("warn,my_crate=debug").on();
+    ```
+    */
+    #[must_use]
+    pub fn filter(&mut self, directives: &str) -> &mut Self {
+        self.filter.directives = directives.to_string();
+        self
+    }
+
+    /**
+    Set the level for a target, added to the directives from `filter()`. Targets match themselves and any module
+    under them:
+    ```
+    use traceon::Level;
+
+    traceon::builder()
+        .filter("warn")
+        .target_level("my_crate", Level::DEBUG)
+        .target_level("my_crate::noisy", Level::ERROR)
+        .on();
+    ```
+    */
+    #[must_use]
+    pub fn target_level(&mut self, target: &str, level: Level) -> &mut Self {
+        self.filter.targets.push((target.to_string(), level));
+        self
+    }
+
+    /**
+    Change the environment variable read for filter directives, the default is `RUST_LOG`. When the variable is set
+    it replaces the directives from `filter()` and `target_level()`:
+    ```
+    traceon::builder().filter_env("MY_APP_LOG").on();
+    ```
+    */
+    #[must_use]
+    pub fn filter_env(&mut self, variable: &'static str) -> &mut Self {
+        self.filter.env_var = Some(variable);
+        self
+    }
+
+    /// Ignore the environment completely and only use the directives from `filter()` and `target_level()`
+    #[must_use]
+    pub fn ignore_filter_env(&mut self) -> &mut Self {
+        self.filter.env_var = None;
+        self
+    }
+
+    /// Turn on the storage, formatting and filter layers as a global default, which means all threads will inherit it but it can
+    /// be overwritten for a single thread with for example: `let _guard = traceon::builder().on_thread();`
+    ///
+    /// Returns a `ReloadHandle` to change the filter and formatting at runtime, which can be ignored if not required
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the filter directives are invalid, or the global default subscriber is already set, use `try_on`
+    /// instead to return a `Result`
+    pub fn on(&self) -> ReloadHandle {
+        match self.try_on() {
+            Ok(handle) => handle,
+            Err(e) => panic!("failed to turn on traceon: {e}"),
+        }
+    }
+
+    /// Turn on the storage, formatting and filter layers as a global default, which means all threads will inherit it but it can
+    /// be overwritten for a single thread with for example: `let _guard = traceon::builder().on_thread();`
+    ///
+    /// Returns a `ReloadHandle` to change the filter and formatting at runtime, or an error if the filter directives
+    /// from the builder or the environment variable are invalid, or the global default subscriber is already set
+    pub fn try_on(&self) -> Result<ReloadHandle, OnError> {
+        let env_filter = self.filter.build()?;
+        let (format_layer, format_handle) = reload::Layer::new(self.clone());
+        let (filter_layer, filter_handle) = reload::Layer::new(env_filter);
+        let subscriber = Registry::default().with(format_layer).with(filter_layer);
+
+        tracing::subscriber::set_global_default(subscriber)?;
+        Ok(ReloadHandle::new(format_handle, filter_handle))
+    }
+
+    /**
+    Turn on the storage, formatting and filter layers on the local thread returning a guard, when the guard is dropped the
+    layers will be unsubscribed.
+
+    # Examples
+
+    ```
+    // Turn on the subscriber with json formatting
+    let _span = tracing::info_span!("the storage layer in this subscriber will have a field", field = "temp");
+    let _guard = traceon::builder().json().on_thread();
+    tracing::info!("first subscriber");
+
+
+    // Drop the previous subscriber and storage for the fields, this new one has pretty formatting
+    let _span = tracing::info!("the storage layer has been reset");
+    let _guard = traceon::builder().on_thread();
+    tracing::info!("second subscriber")
+    ```
+
+    # Panics
+
+    Will panic if the filter directives from the builder or the environment variable are invalid
+    */
+    pub fn on_thread(&self) -> tracing::subscriber::DefaultGuard {
+        let env_filter = self
+            .filter
+            .build()
+            .unwrap_or_else(|e| panic!("failed to turn on traceon: {e}"));
+        let subscriber = Registry::default().with(self.clone()).with(env_filter);
+
+        tracing::subscriber::set_default(subscriber)
+    }
+
+    /// Serialize a single record in `format`, the message and any other fields come from `event_visitor`, and the
+    /// inherited fields from `span` and its parents
+    fn serialize<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
+        &self,
+        format: Format,
+        metadata: &Metadata<'_>,
+        event_visitor: &JsonStorage,
+        span: Option<&SpanRef<'_, S>>,
+        layout: &Layout,
+        out: &mut Vec<u8>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let trace_fields = self.trace_fields(span);
+        // Nested json reads each span's own fields instead of the flattened ones
+        let extensions = span
+            .filter(|_| format != Format::NestedJson)
+            .map(SpanRef::extensions);
+        let mut span_fields = recycle(SPAN_FIELDS.take());
+        if let Some(own_fields) = extensions
+            .as_ref()
+            .and_then(|extensions| extensions.get::<Arc<SpanFields>>())
+        {
+            self.resolve_span_fields(own_fields, format == Format::Pretty, &mut span_fields);
+        }
+        let google_cloud = format == Format::GoogleCloud;
+        let ecs = format == Format::Ecs;
+        // Presets write the built-in fields with the exact keys their platform reads
+        let preset_keys = match format {
+            Format::GoogleCloud => Some(&google_cloud::KEYS),
+            Format::Ecs => Some(&ecs::KEYS),
+            _ => None,
+        };
+        let keys = preset_keys.unwrap_or(&self.keys);
+        let key = |key: &'static str| -> Cow<'static, str> {
+            match preset_keys {
+                Some(_) => Cow::Borrowed(key),
+                None => self.case_key(key),
             }
-            let key = match self.case {
-                Case::Snake => snake(key),
-                Case::Pascal => pascal(key),
-                Case::Camel => camel(key),
-                Case::None => key.to_string(),
+        };
+
+        // Built-in keys first, then the event fields in the order they're declared, the span fields from the root
+        // to the current span, and the default fields last
+        let mut entries: Vec<Entry> = recycle(ENTRIES.take());
+
+        if self.time != TimeFormat::None {
+            let time_string = match self.timezone {
+                // Cloud Logging only reads RFC 3339 times
+                _ if google_cloud => Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true),
+                _ if ecs => Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                TimeZone::UTC => {
+                    let now = Utc::now();
+                    time_convert(now, &self.time)
+                }
+                TimeZone::Local => {
+                    let now = Local::now();
+                    time_convert(now, &self.time)
+                }
+            };
+            entries.push(Entry::headline(
+                "time",
+                key(keys.time),
+                Value::from(time_string),
+            ));
+        }
+
+        let level = match self.level {
+            _ if google_cloud => Some(Cow::Owned(Value::from(google_cloud::severity(
+                metadata.level(),
+            )))),
+            _ if ecs => level_value(LevelFormat::Lowercase, metadata.level()).map(Cow::Borrowed),
+            level_format => level_value(level_format, metadata.level()).map(Cow::Borrowed),
+        };
+        if let Some(level) = level {
+            entries.push(Entry {
+                name: "level",
+                key: key(keys.level),
+                value: Json::Value(level),
+                headline: true,
+            });
+        }
+
+        if let Some(message) = event_visitor.values.get("message") {
+            entries.push(Entry {
+                name: "message",
+                key: key(keys.message),
+                value: Json::from(message),
+                headline: true,
+            });
+        }
+
+        if google_cloud {
+            entries.push(Entry::new(
+                "source_location",
+                Cow::Borrowed(SOURCE_LOCATION_KEY),
+                Json::Object(google_cloud::source_location(metadata)),
+            ));
+        }
+
+        if ecs {
+            entries.push(Entry::new(
+                "ecs_version",
+                Cow::Borrowed(ecs::VERSION_KEY),
+                Value::from(ecs::VERSION),
+            ));
+            let module = metadata.module_path().unwrap_or_default();
+            entries.push(Entry::new("module", key(keys.module), Value::from(module)));
+            let file = metadata.file().unwrap_or_default();
+            entries.push(Entry::new("file", key(keys.file), Value::from(file)));
+            let line = metadata.line().unwrap_or_default();
+            entries.push(Entry::new(
+                "line",
+                Cow::Borrowed(ecs::LINE_KEY),
+                Value::from(line),
+            ));
+        }
+
+        if self.module && preset_keys.is_none() {
+            let value = metadata.module_path().unwrap_or_default();
+            entries.push(Entry::new("module", key(keys.module), Value::from(value)));
+        }
+
+        if self.file && preset_keys.is_none() {
+            let value = format!(
+                "{}:{}",
+                metadata.file().unwrap_or_default(),
+                metadata.line().unwrap_or_default()
+            );
+            entries.push(Entry::new("file", key(keys.file), Value::from(value)));
+        }
+
+        for (name, value) in trace_fields {
+            let preset_field = match format {
+                Format::GoogleCloud => {
+                    google_cloud::trace_field(name, &value, self.google_project.as_deref())
+                }
+                Format::Ecs => ecs::trace_field(name, &value),
+                _ => None,
             };
+            entries.push(match preset_field {
+                Some((preset_key, value)) => Entry::new(name, Cow::Borrowed(preset_key), value),
+                None => {
+                    let value = Value::from(value);
+                    let key = self.field_key(format, self.trace_key(name), &value);
+                    Entry::new(name, key, value)
+                }
+            });
+        }
 
-            if self.json {
-                map_serializer.serialize_entry(&key, value)?;
-            } else if !key.eq_ignore_ascii_case("message") {
-                fields.push((key.to_string(), clean_json_value(value)));
+        // Event fields other than the message go under their own key for nested json
+        let mut nested_fields = Object::default();
+        for (name, value) in event_visitor.values.iter() {
+            if name == "message" {
+                continue;
+            }
+            let key = self.field_key(format, name, value);
+            if format == Format::NestedJson {
+                nested_fields.insert(key, Json::from(value));
+            } else {
+                let value = match format {
+                    Format::Pretty => event_visitor.pretty_value(name, value),
+                    _ => value,
+                };
+                entries.push(Entry {
+                    name,
+                    key,
+                    value: Json::from(value),
+                    headline: false,
+                });
             }
         }
 
-        // Add all the fields from the current span, if we have one.
-        if let Some(span) = &current_span {
-            let extensions = span.extensions();
-            if let Some(visitor) = extensions.get::<JsonStorage>() {
-                for (key, value) in &visitor.values {
-                    let key = match self.case {
-                        Case::Snake => snake(key),
-                        Case::Pascal => pascal(key),
-                        Case::Camel => camel(key),
-                        Case::None => key.to_string(),
-                    };
-
-                    if self.json {
-                        map_serializer.serialize_entry(&key, value)?;
-                    } else if !key.eq_ignore_ascii_case("message") {
-                        fields.push((key.to_string(), clean_json_value(value)));
+        if format == Format::NestedJson {
+            if !nested_fields.is_empty() {
+                entries.push(Entry::new(
+                    "fields",
+                    key(keys.fields),
+                    Json::Object(nested_fields),
+                ));
+            }
+            // Each span with only its own fields, from the root to the current span
+            if let Some(span) = span {
+                let mut spans = Vec::new();
+                for span in span.scope().from_root() {
+                    let mut nested_span = Object::default();
+                    nested_span.insert(
+                        self.case_key(keys.name),
+                        Json::from(Value::from(span.name())),
+                    );
+                    if let Some(span_fields) = span.extensions().get::<Arc<SpanFields>>() {
+                        for (key, value) in span_fields.fields.values.iter() {
+                            nested_span
+                                .insert(self.case_key(key).into_owned(), Json::from(value.clone()));
+                        }
                     }
+                    spans.push(Json::Object(nested_span));
                 }
+                entries.push(Entry::new("spans", key(keys.spans), Json::Array(spans)));
+            }
+        }
+
+        // Add all the fields from the current span and its parents
+        for (name, value) in &span_fields {
+            let key = self.field_key(format, name, value);
+            if format == Format::Pretty && key.eq_ignore_ascii_case("message") {
+                continue;
+            }
+            entries.push(Entry {
+                name,
+                key,
+                value: Json::from(value.as_ref()),
+                headline: false,
+            });
+        }
+
+        // Default fields have the lowest priority, so they're skipped if the event or a span has the same key
+        for (name, value) in self.default_fields.iter() {
+            if event_visitor.values.contains_key(name)
+                || span_fields.iter().any(|(existing, _)| existing == name)
+            {
+                continue;
             }
+            entries.push(Entry {
+                name,
+                key: self.field_key(format, name, value),
+                value: Json::from(value),
+                headline: false,
+            });
         }
-        if !self.json {
-            fields.sort_by(|a, b| a.0.cmp(&b.0));
-            let mut max_len = 0;
-            for field in &fields {
-                if field.0.len() > max_len {
-                    max_len = field.0.len();
+
+        // Pinned keys move to the front in the order they were given, everything else keeps its place
+        if !self.field_order.is_empty() {
+            entries.sort_by_key(|entry| {
+                self.field_order
+                    .iter()
+                    .position(|pinned| pinned == entry.name || pinned == entry.key.as_ref())
+                    .unwrap_or(usize::MAX)
+            });
+        }
+
+        match format {
+            Format::Json | Format::NestedJson | Format::GoogleCloud | Format::Ecs => {
+                let start = out.len();
+                if ecs {
+                    let record =
+                        ecs::nest(entries.iter().filter_map(|entry| {
+                            Some((entry.key.as_ref(), entry.value.as_value()?))
+                        }));
+                    serde_json::to_writer(&mut *out, &record)?;
+                } else {
+                    let mut serializer = serde_json::Serializer::new(&mut *out);
+                    let mut map_serializer = serializer.serialize_map(Some(entries.len()))?;
+                    for entry in &entries {
+                        map_serializer.serialize_entry(&entry.key, &entry.value)?;
+                    }
+                    map_serializer.end()?;
+                }
+                if let Some(theme) = layout.theme.filter(|_| self.colored_json) {
+                    let painted = paint_json(&out[start..], theme);
+                    out.truncate(start);
+                    out.extend_from_slice(&painted);
                 }
             }
-            for field in fields {
-                let mut seperator = ": ".to_string();
-                let spaces = max_len - field.0.len();
-                for _ in 0..spaces {
-                    seperator += " ";
+            Format::Logfmt => {
+                let values = entries
+                    .iter()
+                    .filter_map(|entry| Some((&entry.key, entry.value.as_value()?)));
+                for (i, (key, value)) in values.enumerate() {
+                    if i > 0 {
+                        out.push(b' ');
+                    }
+                    logfmt_entry(out, key, value)?;
                 }
-                writeln!(pretty_buffer, "    {}{}{}", field.0, seperator, field.1)?;
+            }
+            Format::Pretty => {
+                // The buffer only ever holds records, which are utf-8, so it's laid out in place as a string
+                let mut text = String::from_utf8(std::mem::take(out)).unwrap_or_default();
+                pretty(&mut text, metadata, &entries, layout);
+                *out = text.into_bytes();
             }
         }
-        map_serializer.end()?;
-        if self.json {
-            Ok(json_buffer)
-        } else {
-            Ok(pretty_buffer)
+        ENTRIES.set(recycle(entries));
+        SPAN_FIELDS.set(recycle(span_fields));
+        Ok(())
+    }
+
+    /// Resolve the fields of a span and its parents from the root, a child's field replaces or joins the field of the
+    /// same name on its parents, and the span key has the current span's value
+    fn resolve_span_fields<'a>(
+        &self,
+        span_fields: &'a SpanFields,
+        pretty: bool,
+        fields: &mut Vec<(&'a str, Cow<'a, Value>)>,
+    ) {
+        if let Some(parent) = &span_fields.parent {
+            self.resolve_span_fields(parent, pretty, fields);
+        }
+        if let Some(span) = &span_fields.span {
+            insert_field(fields, self.keys.span, Cow::Borrowed(span));
+        }
+        for (name, value) in span_fields.fields.values.iter() {
+            let value = if pretty {
+                span_fields.fields.pretty_value(name, value)
+            } else {
+                value
+            };
+            let inherited = fields
+                .iter()
+                .find(|(existing, _)| *existing == name)
+                .map(|(_, inherited)| inherited.as_ref());
+            let value = span_fields.resolve(name, value, inherited);
+            insert_field(fields, name, value);
+        }
+    }
+
+    /// Write a record for a span lifecycle event, the span's own fields are used as the inherited fields
+    fn serialize_span_event<
+        S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    >(
+        &self,
+        span: &tracing_subscriber::registry::SpanRef<'_, S>,
+        lifecycle: &str,
+        timings: Option<&Timings>,
+    ) {
+        let mut event_visitor = self.storage();
+        event_visitor.values.insert(
+            "message",
+            Value::from(format!("{} [{lifecycle}]", span.name())),
+        );
+        if let Some(timings) = timings {
+            let elapsed = timings.created.elapsed();
+            let idle = timings.idle + timings.last.elapsed();
+            event_visitor
+                .values
+                .insert("elapsed_milliseconds", Value::from(milliseconds(elapsed)));
+            event_visitor
+                .values
+                .insert("busy_milliseconds", Value::from(milliseconds(timings.busy)));
+            event_visitor
+                .values
+                .insert("idle_milliseconds", Value::from(milliseconds(idle)));
+        }
+        self.record(span.metadata(), &event_visitor, Some(span));
+    }
+
+    /// Empty storage for the fields of a new event or root span
+    fn storage(&self) -> JsonStorage<'static> {
+        let mut storage = JsonStorage::new(self.join_fields, self.span_format);
+        storage.redactions = self.redactions.clone();
+        storage.alternate_debug = self.alternate_debug && self.writes_pretty();
+        storage
+    }
+
+    /// If the default format or any of the routes write pretty output
+    fn writes_pretty(&self) -> bool {
+        self.format == Format::Pretty
+            || self
+                .routes
+                .iter()
+                .any(|route| route.format == Some(Format::Pretty))
+    }
+
+    /// Convert a key to the configured case, unless it's exempt
+    fn case_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if self.case_exempt.iter().any(|exempt| exempt == key) {
+            return Cow::Borrowed(key);
+        }
+        convert_case(key, &self.case)
+    }
+
+    /// Names and values of the trace context for the span a record is in, the ids from `trace_ids()` take priority
+    /// over the OpenTelemetry ids
+    fn trace_fields<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
+        &self,
+        span: Option<&SpanRef<'_, S>>,
+    ) -> Vec<(&'static str, String)> {
+        let mut trace_fields = Vec::new();
+        let Some(span) = span else {
+            return trace_fields;
+        };
+        if self.trace_ids
+            && let Some(context) = span.extensions().get::<TraceContext>()
+        {
+            trace_fields.push(("trace_id", format!("{:032x}", context.trace_id)));
+            trace_fields.push(("span_id", format!("{:016x}", context.span_id)));
+            if let Some(parent_span_id) = context.parent_span_id {
+                trace_fields.push(("parent_span_id", format!("{parent_span_id:016x}")));
+            }
+            return trace_fields;
+        }
+        #[cfg(feature = "opentelemetry")]
+        if let Some(ids) = crate::otel::otel_ids(span) {
+            trace_fields.push(("trace_id", ids.trace_id));
+            trace_fields.push(("span_id", ids.span_id));
+            if let Some(trace_flags) = ids.trace_flags {
+                trace_fields.push(("trace_flags", trace_flags));
+            }
+        }
+        trace_fields
+    }
+
+    /// Key for an event, span or trace field in `format`, in ECS the span name is `span.name` and fields that
+    /// aren't in their own namespace go under `labels`
+    fn field_key<'a>(&self, format: Format, name: &'a str, value: &Value) -> Cow<'a, str> {
+        if format != Format::Ecs {
+            return self.case_key(name);
+        }
+        if name == self.keys.span {
+            return Cow::Borrowed(ecs::KEYS.span);
+        }
+        ecs::field_key(self.case_key(name), value)
+    }
+
+    /// Key from `trace_keys()` for a trace field
+    fn trace_key(&self, name: &str) -> &'static str {
+        match name {
+            "trace_id" => self.trace_keys.trace_id,
+            "span_id" => self.trace_keys.span_id,
+            "parent_span_id" => self.trace_keys.parent_span_id,
+            _ => self.trace_keys.trace_flags,
+        }
+    }
+
+    /// Colors and wrapping for a record written to `writer` in `format`
+    fn layout(&self, format: Format, writer: &Writer) -> Layout<'_> {
+        let colored = match self.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => self.env_colors.unwrap_or(writer.is_terminal()),
+        };
+        let width = match self.wrap {
+            _ if format != Format::Pretty => None,
+            Wrap::Terminal => writer.width(),
+            Wrap::Width(width) => Some(width),
+            Wrap::None => None,
+        };
+        Layout {
+            theme: colored.then_some(&self.theme),
+            width,
+            json_strings: self.json_strings,
+        }
+    }
+
+    /// Serialize and write a record to the first route that matches, or the default writer and format
+    fn record<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
+        &self,
+        metadata: &Metadata<'_>,
+        event_visitor: &JsonStorage,
+        span: Option<&SpanRef<'_, S>>,
+    ) {
+        let (format, writer) = match self.routes.iter().find(|route| route.matches(metadata)) {
+            Some(route) => (route.format.unwrap_or(self.format), &route.writer),
+            None => (self.format, &self.writer),
+        };
+        let layout = self.layout(format, writer);
+        // The buffer is taken from the thread while it's in use, so a writer that logs gets a new one
+        let mut buffer = OUTPUT.take();
+        let result = self.serialize(format, metadata, event_visitor, span, &layout, &mut buffer);
+        self.write(format, writer, result, &mut buffer);
+        buffer.clear();
+        OUTPUT.set(buffer);
+    }
+
+    /// Write a serialized record followed by a newline
+    fn write(
+        &self,
+        format: Format,
+        writer: &Writer,
+        result: Result<(), Box<dyn std::error::Error>>,
+        buffer: &mut Vec<u8>,
+    ) {
+        match result {
+            Ok(()) => {
+                buffer.push(b'\n');
+                match writer {
+                    Writer::Locked(writer) => writer.lock().unwrap().write_all(buffer).unwrap(),
+                    Writer::Stdout(_) => std::io::stdout().lock().write_all(buffer).unwrap(),
+                    Writer::Stderr(_) => std::io::stderr().lock().write_all(buffer).unwrap(),
+                    Writer::NonBlocking(non_blocking) => {
+                        // The writer thread owns the record, the thread's buffer keeps its capacity
+                        non_blocking.push(buffer.to_vec());
+                        non_blocking.report_dropped(|| {
+                            let traceon = self.clone();
+                            let writer = writer.clone();
+                            Arc::new(move |dropped| {
+                                traceon.serialize_dropped(
+                                    format,
+                                    dropped,
+                                    &traceon.layout(format, &writer),
+                                )
+                            })
+                        });
+                    }
+                }
+            }
+            Err(e) => {
+                dbg!(e);
+            }
+        }
+    }
+
+    /// Write a follow-up record with the `suppressed_count` for each window of repeats
+    fn record_suppressed<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
+        &self,
+        suppressed: Vec<Suppressed>,
+    ) {
+        for mut suppressed in suppressed {
+            suppressed
+                .fields
+                .values
+                .insert("suppressed_count", Value::from(suppressed.count));
+            self.record::<S>(suppressed.metadata, &suppressed.fields, None);
+        }
+    }
+
+    /// Serialize the warning for records dropped by a `NonBlocking` writer
+    fn serialize_dropped(&self, format: Format, dropped: u64, layout: &Layout) -> Option<Vec<u8>> {
+        let mut event_visitor = self.storage();
+        event_visitor.values.insert(
+            "message",
+            Value::from(format!(
+                "dropped {dropped} records because the writer queue was full"
+            )),
+        );
+        event_visitor
+            .values
+            .insert("dropped_count", Value::from(dropped));
+        let mut buffer = Vec::new();
+        match self.serialize::<Registry>(
+            format,
+            &DROPPED_METADATA,
+            &event_visitor,
+            None,
+            layout,
+            &mut buffer,
+        ) {
+            Ok(()) => {
+                buffer.push(b'\n');
+                Some(buffer)
+            }
+            Err(e) => {
+                eprintln!("traceon failed to serialize the dropped records warning: {e}");
+                None
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// Output buffer reused by every record written on the thread
+    static OUTPUT: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+    /// Allocation reused for the entries of every record written on the thread
+    static ENTRIES: Cell<Vec<Entry<'static>>> = const { Cell::new(Vec::new()) };
+    /// Allocation reused for the fields of every event recorded on the thread
+    static EVENT_FIELDS: Cell<Vec<(&'static str, Value)>> = const { Cell::new(Vec::new()) };
+    /// Allocation reused for the resolved span fields of every record written on the thread
+    static SPAN_FIELDS: Cell<Vec<(&'static str, Cow<'static, Value>)>> = const { Cell::new(Vec::new()) };
+}
+
+/// Empty a vec and keep its allocation for another type of the same size and alignment, e.g. entries that borrow
+/// from a different record. Collecting a `vec::IntoIter` into a vec reuses the allocation when the layouts match.
+fn recycle<T, U>(mut vec: Vec<T>) -> Vec<U> {
+    vec.clear();
+    vec.into_iter()
+        .map(|_| unreachable!("the vec is empty"))
+        .collect()
+}
+
+/// The level as a json value for each `LevelFormat`, built once so records don't allocate them
+fn level_value(level_format: LevelFormat, level: &Level) -> Option<&'static Value> {
+    static LEVELS: LazyLock<[[Value; 5]; 3]> = LazyLock::new(|| {
+        let levels = [
+            Level::TRACE,
+            Level::DEBUG,
+            Level::INFO,
+            Level::WARN,
+            Level::ERROR,
+        ];
+        [
+            levels.map(|level| Value::from(level.as_str())),
+            levels.map(|level| Value::from(level.as_str().to_ascii_lowercase())),
+            [10, 20, 30, 40, 50].map(Value::from),
+        ]
+    });
+    let format = match level_format {
+        LevelFormat::Uppercase => 0,
+        LevelFormat::Lowercase => 1,
+        LevelFormat::Number => 2,
+        LevelFormat::None => return None,
+    };
+    let level = match *level {
+        Level::TRACE => 0,
+        Level::DEBUG => 1,
+        Level::INFO => 2,
+        Level::WARN => 3,
+        Level::ERROR => 4,
+    };
+    Some(&LEVELS[format][level])
+}
+
+/// A key and value to write in a record
+struct Entry<'a> {
+    /// Name of the field as it was recorded, or of the built-in field
+    name: &'a str,
+    /// Key written to the output
+    key: Cow<'a, str>,
+    /// Objects and arrays are only built for the json formats
+    value: Json<'a>,
+    /// Time, level and message, which pretty output writes on the headline
+    headline: bool,
+}
+
+impl<'a> Entry<'a> {
+    fn new(name: &'a str, key: Cow<'a, str>, value: impl Into<Json<'a>>) -> Entry<'a> {
+        Entry {
+            name,
+            key,
+            value: value.into(),
+            headline: false,
+        }
+    }
+
+    fn headline(name: &'a str, key: Cow<'a, str>, value: Value) -> Entry<'a> {
+        Entry {
+            headline: true,
+            ..Entry::new(name, key, value)
+        }
+    }
+}
+
+/// Write a record with the time, level and message on a colored headline, and the other fields indented below it
+fn pretty(pretty: &mut String, metadata: &Metadata<'_>, entries: &[Entry], layout: &Layout) {
+    let level_style = layout.theme.map(|theme| theme.level(metadata.level()));
+    let mut headline = false;
+    let mut message = Cow::Borrowed("event triggered");
+    for entry in entries.iter().filter(|entry| entry.headline) {
+        let Some(value) = entry.value.as_value() else {
+            continue;
+        };
+        let style = match entry.name {
+            "message" => {
+                message = text(value);
+                continue;
+            }
+            "time" => layout.theme.map(|theme| theme.time),
+            _ => level_style,
+        };
+        if headline {
+            pretty.push(' ');
+        }
+        paint(pretty, style, &text(value));
+        headline = true;
+    }
+
+    let message = message.trim();
+    if !message.is_empty() {
+        if headline {
+            pretty.push(' ');
+        }
+        // Lines after the first are indented like the fields
+        write_text(pretty, message, level_style, 4, None);
+    }
+
+    let fields: Vec<(Label, &Value)> = entries
+        .iter()
+        .filter(|entry| !entry.headline)
+        .filter_map(|entry| Some((Label::Key(&entry.key), entry.value.as_value()?)))
+        .collect();
+    write_entries(pretty, &fields, 4, layout);
+    pretty.push('\n');
+}
+
+/// Where serialized records are written to
+#[derive(Clone)]
+pub(crate) enum Writer {
+    /// Write on the thread that is logging while holding a lock
+    Locked(Arc<Mutex<dyn Write + Sync + Send>>),
+    /// Write to stdout, `true` if it's a terminal
+    Stdout(bool),
+    /// Write to stderr, `true` if it's a terminal
+    Stderr(bool),
+    /// Queue the record for a dedicated writer thread
+    NonBlocking(NonBlocking),
+}
+
+impl Writer {
+    pub fn stdout() -> Writer {
+        Writer::Stdout(std::io::stdout().is_terminal())
+    }
+
+    pub fn stderr() -> Writer {
+        Writer::Stderr(std::io::stderr().is_terminal())
+    }
+
+    /// Only stdout and stderr are checked, any other writer is treated as a file
+    fn is_terminal(&self) -> bool {
+        match self {
+            Writer::Stdout(terminal) | Writer::Stderr(terminal) => *terminal,
+            Writer::Locked(_) | Writer::NonBlocking(_) => false,
+        }
+    }
+
+    /// Columns of the terminal, checked on each record as it can be resized
+    fn width(&self) -> Option<usize> {
+        let size = match self {
+            Writer::Stdout(true) => terminal_size::terminal_size_of(std::io::stdout()),
+            Writer::Stderr(true) => terminal_size::terminal_size_of(std::io::stderr()),
+            _ => None,
+        };
+        size.map(|(terminal_size::Width(width), _)| usize::from(width))
+    }
+}
+
+/**
+The fields declared and recorded on a span without any inherited from its parents, stored in the span's extensions.
+Children share their parent's fields instead of copying them, the fields of the whole scope are resolved from the root
+when a record is written. A span that records a field while it has children copies its fields first, so the children
+keep the values they inherited when they were created.
+*/
+#[derive(Clone)]
+struct SpanFields {
+    fields: JsonStorage<'static>,
+    /// Value of the span key, the span's name joined to its parents' with `SpanFormat::Join`, `None` with
+    /// `SpanFormat::None`
+    span: Option<Value>,
+    /// Fields of the parent span as they were when this span was created
+    parent: Option<Arc<SpanFields>>,
+}
+
+impl SpanFields {
+    /// Value of a field after joining or replacing the value inherited from the parent spans
+    fn resolve<'a>(
+        &'a self,
+        name: &str,
+        value: &'a Value,
+        inherited: Option<&Value>,
+    ) -> Cow<'a, Value> {
+        match (self.fields.join(name), inherited) {
+            (Some(chars), Some(inherited)) => Cow::Owned(Value::from(format!(
+                "{}{chars}{}",
+                inherited.as_str().unwrap_or(""),
+                value.as_str().unwrap_or("")
+            ))),
+            _ => Cow::Borrowed(value),
+        }
+    }
+}
+
+/// Insert or replace a resolved span field, a field that's replaced keeps its place
+fn insert_field<'a>(
+    fields: &mut Vec<(&'a str, Cow<'a, Value>)>,
+    name: &'a str,
+    value: Cow<'a, Value>,
+) {
+    match fields.iter_mut().find(|(existing, _)| *existing == name) {
+        Some((_, existing)) => *existing = value,
+        None => fields.push((name, value)),
+    }
+}
+
+/// Timings stored in the span's extensions when `SpanEvents` are on
+struct Timings {
+    created: Instant,
+    last: Instant,
+    busy: Duration,
+    idle: Duration,
+}
+
+impl Timings {
+    fn new() -> Self {
+        let now = Instant::now();
+        Timings {
+            created: now,
+            last: now,
+            busy: Duration::ZERO,
+            idle: Duration::ZERO,
+        }
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl Drop for Traceon {
+    /// The last clone of the layer writes the repeats from dedup windows that are still open
+    fn drop(&mut self) {
+        if let Some(dedup) = self.dedup.take() {
+            self.record_suppressed::<Registry>(dedup.pending());
         }
     }
 }
@@ -594,205 +2036,444 @@ where
     S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
 {
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
-        let mut event_visitor = JsonStorage::new(self.join_fields, self.span_format);
-        match self.serialize(event, ctx, &mut event_visitor) {
-            Ok(mut buffer) => {
-                buffer.write_all(b"\n").unwrap();
-                self.writer.lock().unwrap().write_all(&buffer).unwrap();
-            }
-            Err(e) => {
-                dbg!(e);
-            }
+        let mut event_visitor = self.storage();
+        event_visitor.values.0 = EVENT_FIELDS.take();
+        event.record(&mut event_visitor);
+        let mut write = true;
+        if let Some(dedup) = &self.dedup {
+            let closed;
+            (write, closed) = dedup.check(event.metadata(), &event_visitor);
+            self.record_suppressed::<S>(closed);
         }
+        if write {
+            let current_span = ctx.lookup_current();
+            self.record(event.metadata(), &event_visitor, current_span.as_ref());
+        }
+        let mut fields = event_visitor.values.0;
+        fields.clear();
+        EVENT_FIELDS.set(fields);
     }
 
     /// This is the only occasion we have to store the fields attached to the span
     fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("Span not found, this is a bug");
-        let span_key = match self.case {
-            Case::Pascal => "Span",
-            _ => "span",
+        let span_key = self.keys.span;
+        // Children join the trace of their parent, root spans continue a `traceparent` field or the environment
+        let parent_context = if !self.trace_ids {
+            None
+        } else if let Some(parent_span) = span.parent() {
+            parent_span
+                .extensions()
+                .get::<TraceContext>()
+                .map(TraceContext::traceparent)
+        } else {
+            let mut traceparent = TraceParentVisitor::default();
+            attrs.record(&mut traceparent);
+            traceparent.0.or(self.env_traceparent)
         };
-        // We want to inherit the fields from the parent span, if there is one.
-        let mut visitor = if let Some(parent_span) = span.parent() {
-            let mut extensions = parent_span.extensions_mut();
-            let mut storage = extensions
-                .get_mut::<JsonStorage>()
-                .map(|v| v.to_owned())
-                .unwrap_or_default();
-            if self.span_format != SpanFormat::None
-                && let Some(orig) = storage
-                    .values
-                    .insert(span_key, serde_json::Value::from(span.metadata().name()))
-            {
-                match self.span_format {
-                    SpanFormat::Overwrite => (),
-                    SpanFormat::Join(concat) => {
-                        storage.values.insert(
-                            span_key,
-                            serde_json::Value::from(format!(
-                                "{}{}{}",
-                                orig.as_str().unwrap_or(""),
-                                concat,
-                                span.metadata().name()
-                            )),
-                        );
+        let parent_fields = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<Arc<SpanFields>>().cloned());
+        // Only the span value is resolved when the span is created, it's the parent's joined with this span's name
+        let name = span.metadata().name();
+        let span_value = match self.span_format {
+            SpanFormat::None => None,
+            SpanFormat::Overwrite => Some(Value::from(name)),
+            SpanFormat::Join(concat) => {
+                let parent_value = parent_fields.as_deref().and_then(|parent| {
+                    // A field recorded with the same name as the span key replaces or joins the span value
+                    let inherited = parent.span.as_ref();
+                    match parent.fields.values.get(span_key) {
+                        Some(value) => Some(parent.resolve(span_key, value, inherited)),
+                        None => inherited.map(Cow::Borrowed),
                     }
-                    SpanFormat::None => (),
-                }
-            };
-            storage
-        } else {
-            let mut storage = JsonStorage::new(self.join_fields, self.span_format);
-            if self.span_format != SpanFormat::None {
-                storage
-                    .values
-                    .insert(span_key, serde_json::Value::from(span.metadata().name()));
+                });
+                Some(match parent_value {
+                    Some(parent_value) => Value::from(format!(
+                        "{}{concat}{name}",
+                        parent_value.as_str().unwrap_or("")
+                    )),
+                    None => Value::from(name),
+                })
             }
-            storage
         };
 
+        let mut fields = self.storage();
+        fields.joins = Some(Vec::new());
+        // Fields on the new span override or join the fields of the parent spans when a record is written
+        attrs.record(&mut fields);
         let mut extensions = span.extensions_mut();
-        // Fields on the new span should override fields on the parent span if there is a conflict.
-        attrs.record(&mut visitor);
-        // Associate the visitor with the Span for future usage via the Span's extensions
-        extensions.insert(visitor);
+        extensions.insert(Arc::new(SpanFields {
+            fields,
+            span: span_value,
+            parent: parent_fields,
+        }));
+
+        if self.trace_ids {
+            extensions.insert(TraceContext::new(parent_context));
+        }
+
+        if self.span_events != SpanEvents::None {
+            extensions.insert(Timings::new());
+        }
+        drop(extensions);
+
+        if matches!(self.span_events, SpanEvents::NewAndClose | SpanEvents::Full) {
+            self.serialize_span_event(&span, "START", None);
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if self.span_events == SpanEvents::None {
+            return;
+        }
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+            let now = Instant::now();
+            timings.idle += now - timings.last;
+            timings.last = now;
+        }
+        if self.span_events == SpanEvents::Full {
+            self.serialize_span_event(&span, "ENTER", None);
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if self.span_events == SpanEvents::None {
+            return;
+        }
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+            let now = Instant::now();
+            timings.busy += now - timings.last;
+            timings.last = now;
+        }
+        if self.span_events == SpanEvents::Full {
+            self.serialize_span_event(&span, "EXIT", None);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if self.span_events == SpanEvents::None {
+            return;
+        }
+        let span = ctx.span(&id).expect("Span not found, this is a bug");
+        let timings = span.extensions_mut().remove::<Timings>();
+        self.serialize_span_event(&span, "END", timings.as_ref());
     }
 
     fn on_record(&self, span: &Id, values: &tracing::span::Record<'_>, ctx: Context<'_, S>) {
         let span = ctx.span(span).expect("Span not found, this is a bug");
         let mut extensions = span.extensions_mut();
-        let visitor = extensions
-            .get_mut::<JsonStorage>()
-            .expect("Visitor not found on 'record', this is a bug");
-        values.record(visitor);
+        let span_fields = extensions
+            .get_mut::<Arc<SpanFields>>()
+            .expect("Span fields not found on 'record', this is a bug");
+        // Children share the fields, so they're copied first if there are any
+        values.record(&mut Arc::make_mut(span_fields).fields);
+    }
+}
+
+/// Recorded fields in the order they were first recorded, a field that's recorded again keeps its place
+#[derive(Clone, Debug, Default)]
+pub struct Fields<'a>(Vec<(&'a str, Value)>);
+
+impl<'a> Fields<'a> {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.iter()
+            .find(|(existing, _)| *existing == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.0
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Insert or replace a field, returning the value it replaced
+    pub fn insert(&mut self, name: &'a str, value: Value) -> Option<Value> {
+        match self.get_mut(name) {
+            Some(existing) => Some(std::mem::replace(existing, value)),
+            None => {
+                self.0.push((name, value));
+                None
+            }
+        }
+    }
+
+    /// Remove a field, returning its value
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        let index = self.0.iter().position(|(existing, _)| *existing == name)?;
+        Some(self.0.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &Value)> {
+        self.0.iter().map(|(name, value)| (*name, value))
     }
 }
 
 /// Responsible for storing fields as a set of keys and JSON values when visiting a span
 #[derive(Clone, Debug, Default)]
 pub struct JsonStorage<'a> {
-    pub values: HashMap<&'a str, serde_json::Value>,
+    pub values: Fields<'a>,
     pub join_fields: JoinFields,
     pub span_format: SpanFormat,
+    redactions: Arc<Vec<Redaction>>,
+    alternate_debug: bool,
+    /// For a span's fields, the fields that join the field of the same name on the parent spans and the characters
+    /// to join them with
+    joins: Option<Vec<(&'a str, &'static str)>>,
+    /// `Debug` fields in the alternate `{:#?}` format for pretty output, where it's different from `{:?}`
+    alternates: Fields<'a>,
 }
 
 impl<'a> JsonStorage<'a> {
     pub fn new(join_fields: JoinFields, span_format: SpanFormat) -> Self {
         JsonStorage {
-            values: HashMap::new(),
+            values: Fields::default(),
             join_fields,
             span_format,
+            redactions: Arc::default(),
+            alternate_debug: false,
+            joins: None,
+            alternates: Fields::default(),
+        }
+    }
+
+    /// Value of a field in pretty output, which is `{:#?}` for `Debug` fields with `alternate_debug()` on
+    fn pretty_value<'v>(&'v self, name: &str, value: &'v Value) -> &'v Value {
+        self.alternates.get(name).unwrap_or(value)
+    }
+
+    /// Insert a `Debug` field, and its alternate format if it's needed for pretty output
+    fn insert_debug(&mut self, name: &'a str, value: &dyn std::fmt::Debug) {
+        let recorded = debug_value(value, false);
+        let alternate = self
+            .alternate_debug
+            .then(|| debug_value(value, true))
+            .filter(|alternate| *alternate != recorded);
+        self.insert(name, recorded);
+        if let Some(alternate) = alternate.and_then(|alternate| self.redacted(name, alternate)) {
+            self.alternates.insert(name, alternate);
         }
     }
+
+    /// Characters to join a field to the field of the same name on the parent spans, `None` if it replaces it
+    fn join(&self, name: &str) -> Option<&'static str> {
+        self.joins
+            .iter()
+            .flatten()
+            .find(|(joined, _)| *joined == name)
+            .map(|(_, chars)| *chars)
+    }
+
+    /// Set whether a span's field joins the parent spans, the fields of an event are never joined
+    fn set_join(&mut self, name: &'a str, chars: Option<&'static str>) {
+        let Some(joins) = &mut self.joins else {
+            return;
+        };
+        joins.retain(|(joined, _)| *joined != name);
+        if let Some(chars) = chars {
+            joins.push((name, chars));
+        }
+    }
+
+    /// Apply any redactions to a recorded field, returning `None` if it should be removed
+    fn redacted(&self, name: &str, value: Value) -> Option<Value> {
+        if self.redactions.is_empty() {
+            return Some(value);
+        }
+        redact(&self.redactions, name, value)
+    }
+
+    /// Insert a recorded field after applying any redactions
+    fn insert(&mut self, name: &'a str, value: Value) {
+        if let Some(value) = self.redacted(name, value) {
+            self.values.insert(name, value);
+            self.set_join(name, None);
+            self.alternates.remove(name);
+        }
+    }
+}
+
+fn is_separator(ch: char) -> bool {
+    matches!(ch, '_' | '-' | ' ')
 }
 
-fn snake(key: &str) -> String {
-    let mut snake = String::new();
-    let mut upper_or_underscore_last = false;
-    for (i, ch) in key.char_indices() {
-        if i > 0 && ch.is_uppercase() && !upper_or_underscore_last {
-            snake.push('_');
+/**
+The words in a segment of a key, split at `_`, `-` and spaces, where an uppercase letter follows a lowercase letter or a
+digit, and before the last letter of an acronym followed by a lowercase letter, e.g. `HTTPServer` is `HTTP` and
+`Server`. Letters without case and other characters stay in the word they're in.
+*/
+struct Words<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let word = self.rest.trim_start_matches(is_separator);
+        if word.is_empty() {
+            self.rest = word;
+            return None;
+        }
+        let mut end = word.len();
+        let mut previous: Option<char> = None;
+        let mut chars = word.char_indices().peekable();
+        while let Some((i, ch)) = chars.next() {
+            if is_separator(ch) {
+                end = i;
+                break;
+            }
+            if let Some(previous) = previous
+                && ch.is_uppercase()
+            {
+                let lowercase_next = chars.peek().is_some_and(|(_, next)| next.is_lowercase());
+                if previous.is_lowercase()
+                    || previous.is_numeric()
+                    || (previous.is_uppercase() && lowercase_next)
+                {
+                    end = i;
+                    break;
+                }
+            }
+            previous = Some(ch);
         }
-        upper_or_underscore_last = ch.is_uppercase() || ch == '_';
-        snake.push(ch.to_ascii_lowercase());
+        self.rest = &word[end..];
+        Some(&word[..end])
     }
-    snake
 }
 
-fn pascal(key: &str) -> String {
-    let mut pascal = String::new();
-    let mut capitalize = true;
-    let mut upper_last = false;
-    for ch in key.chars() {
-        if ch.is_lowercase() {
-            upper_last = false;
-        }
-        if ch == '_' {
-            capitalize = true;
-            upper_last = false;
-        } else if upper_last {
-            pascal.push(ch.to_ascii_lowercase());
-        } else if capitalize {
-            pascal.push(ch.to_ascii_uppercase());
-            capitalize = false;
-            upper_last = true;
-        } else {
-            pascal.push(ch);
-            upper_last = false;
+/// Write a key converted to `case`, each segment of a dotted key e.g. `http.request_method` is converted on its own so
+/// the namespaces stay as they are
+fn write_case(out: &mut impl std::fmt::Write, key: &str, case: &Case) -> std::fmt::Result {
+    if matches!(case, Case::None) {
+        return out.write_str(key);
+    }
+    for (i, segment) in key.split('.').enumerate() {
+        if i > 0 {
+            out.write_char('.')?;
+        }
+        write_segment(out, segment, case)?;
+    }
+    Ok(())
+}
+
+/// Write a segment of a key without dots converted to `case`
+fn write_segment(out: &mut impl std::fmt::Write, segment: &str, case: &Case) -> std::fmt::Result {
+    let separator = match case {
+        Case::None => return out.write_str(segment),
+        Case::Camel | Case::Pascal => "",
+        Case::Snake | Case::ScreamingSnake => "_",
+        Case::Kebab => "-",
+        Case::Dot => ".",
+    };
+    for (i, word) in (Words { rest: segment }).enumerate() {
+        if i > 0 {
+            out.write_str(separator)?;
+        }
+        let mut chars = word.chars();
+        let capitalize = matches!(case, Case::Pascal) || (matches!(case, Case::Camel) && i > 0);
+        if capitalize && let Some(first) = chars.next() {
+            first.to_uppercase().try_for_each(|ch| out.write_char(ch))?;
+        }
+        for ch in chars {
+            match case {
+                Case::ScreamingSnake => ch.to_uppercase().try_for_each(|ch| out.write_char(ch))?,
+                _ => ch.to_lowercase().try_for_each(|ch| out.write_char(ch))?,
+            }
         }
     }
-    pascal
+    Ok(())
+}
+
+/// Checks text written to it against a key without allocating, to find keys that are already in the right case
+struct SameAs<'a> {
+    rest: &'a str,
+}
+
+impl std::fmt::Write for SameAs<'_> {
+    fn write_str(&mut self, text: &str) -> std::fmt::Result {
+        // Stop writing at the first difference
+        self.rest = self.rest.strip_prefix(text).ok_or(std::fmt::Error)?;
+        Ok(())
+    }
 }
 
-fn camel(key: &str) -> String {
-    let pascal = pascal(key);
-    pascal[..1].to_ascii_lowercase() + &pascal[1..]
+/// Convert a key to `case`, only allocating if the key changes
+fn convert_case<'a>(key: &'a str, case: &Case) -> Cow<'a, str> {
+    let mut same = SameAs { rest: key };
+    if write_case(&mut same, key, case).is_ok() && same.rest.is_empty() {
+        return Cow::Borrowed(key);
+    }
+    let mut converted = String::with_capacity(key.len());
+    write_case(&mut converted, key, case).expect("writing to a string can't fail");
+    Cow::Owned(converted)
 }
 
-impl Visit for JsonStorage<'_> {
+impl<'a> Visit for JsonStorage<'a> {
     fn record_i64(&mut self, field: &Field, value: i64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
     fn record_u64(&mut self, field: &Field, value: u64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
     fn record_f64(&mut self, field: &Field, value: f64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
     fn record_bool(&mut self, field: &Field, value: bool) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
     fn record_str(&mut self, field: &Field, value: &str) {
-        if let Some(orig) = self
-            .values
-            .insert(field.name(), serde_json::Value::from(value))
-        {
-            if field.name().eq_ignore_ascii_case("span") {
-                if let SpanFormat::Join(chars) = self.span_format {
-                    let orig = orig.as_str().unwrap_or("");
-                    let new = format!("{orig}{chars}{value}");
-                    self.values
-                        .insert(field.name(), serde_json::Value::from(new));
-                }
-            } else {
-                match self.join_fields {
-                    JoinFields::Overwrite => (),
-                    JoinFields::All(chars) => {
-                        let orig = orig.as_str().unwrap_or("");
-                        let new = format!("{orig}{chars}{value}");
-                        self.values
-                            .insert(field.name(), serde_json::Value::from(new));
-                    }
-                    JoinFields::Some(chars, fields) => {
-                        if fields.contains(&field.to_string().as_str()) {
-                            let orig = orig.as_str().unwrap_or("");
-                            let new = format!("{orig}{chars}{value}");
-                            self.values
-                                .insert(field.name(), serde_json::Value::from(new));
-                        }
-                    }
-                }
+        let name = field.name();
+        let Some(value) = self.redacted(name, serde_json::Value::from(value)) else {
+            return;
+        };
+        let join = if name.eq_ignore_ascii_case("span") {
+            match self.span_format {
+                SpanFormat::Join(chars) => Some(chars),
+                _ => None,
+            }
+        } else {
+            match self.join_fields {
+                JoinFields::Overwrite => None,
+                JoinFields::All(chars) => Some(chars),
+                JoinFields::Some(chars, fields) => fields.contains(&name).then_some(chars),
+            }
+        };
+        match (join, self.values.get_mut(name)) {
+            (Some(chars), Some(orig)) => {
+                let new = format!(
+                    "{}{chars}{}",
+                    orig.as_str().unwrap_or(""),
+                    value.as_str().unwrap_or("")
+                );
+                *orig = serde_json::Value::from(new);
+            }
+            (join, _) => {
+                self.values.insert(name, value);
+                self.set_join(name, join);
             }
         }
+        self.alternates.remove(name);
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field.name(), error_value(value));
     }
 
     fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
         match field.name() {
             // Skip fields that are actually log metadata that have already been handled
             name if name.starts_with("log.") => (),
-            name if name.starts_with("r#") => {
-                self.values
-                    .insert(&name[2..], serde_json::Value::from(format!("{:?}", value)));
-            }
-            name => {
-                self.values
-                    .insert(name, serde_json::Value::from(format!("{:?}", value)));
-            }
+            name if name.starts_with("r#") => self.insert_debug(&name[2..], value),
+            name => self.insert_debug(name, value),
         };
     }
 }
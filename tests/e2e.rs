@@ -1,37 +1,62 @@
 use claims::assert_some_eq;
-use once_cell::sync::Lazy;
 use serde_json::{Value, json};
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::io::Write;
+use std::sync::{Arc, Mutex, mpsc};
+use traceon::{
+    Case, Color, ColorMode, Dedup, Format, JoinFields, Keys, NonBlocking, QueueFull, Redact,
+    RollingFile, Route, SpanEvents, SpanFormat, Style, Theme, TimeFormat, TraceParent, Wrap,
+    structured,
+};
 use tracing::{Level, info, span};
-use tracing_subscriber::Registry;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry};
 
-/// Tests have to be run on a single thread because we are re-using the same buffer for
-/// all of them.
-type InMemoryBuffer = Mutex<Vec<u8>>;
-static BUFFER: Lazy<InMemoryBuffer> = Lazy::new(|| Mutex::new(vec![]));
+/// Each test gets its own buffer so they can run in parallel
+type InMemoryBuffer = Arc<Mutex<Vec<u8>>>;
 
 // Run a closure and collect the output emitted by the tracing instrumentation using an in-memory buffer.
 fn run_and_get_raw_output<F: Fn()>(action: F) -> String {
     let mut default_fields = HashMap::new();
     default_fields.insert("custom_field".to_string(), json!("custom_value"));
-    let traceon = traceon::builder();
-    let subscriber = Registry::default().with(traceon);
+    run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .span_events(SpanEvents::Close)
+                .default_fields(default_fields)
+                .buffer(buffer)
+                .clone()
+        },
+        action,
+    )
+}
+
+// Run a closure with a layer built from the closure `layer`, which receives the in-memory buffer to write to.
+fn run_with_layer_and_get_raw_output<L, B, F>(layer: B, action: F) -> String
+where
+    L: Layer<Registry> + Send + Sync,
+    B: FnOnce(InMemoryBuffer) -> L,
+    F: Fn(),
+{
+    let buffer = InMemoryBuffer::default();
+    let subscriber = Registry::default().with(layer(buffer.clone()));
     tracing::subscriber::with_default(subscriber, action);
 
     // Return the formatted output as a string to make assertions against
-    let mut buffer = BUFFER.lock().unwrap();
-    let output = buffer.to_vec();
-    // Clean the buffer to avoid cross-tests interactions
-    buffer.clear();
+    let output = buffer.lock().unwrap().to_vec();
     String::from_utf8(output).unwrap()
 }
 
 // Run a closure and collect the output emitted by the tracing instrumentation using
 // an in-memory buffer as structured new-line-delimited JSON.
 fn run_and_get_output<F: Fn()>(action: F) -> Vec<Value> {
-    run_and_get_raw_output(action)
+    parse_output(&run_and_get_raw_output(action))
+}
+
+// Parse new-line-delimited JSON output into values
+fn parse_output(output: &str) -> Vec<Value> {
+    output
         .lines()
         .filter(|&l| !l.is_empty())
         .inspect(|l| println!("{}", l))
@@ -39,6 +64,40 @@ fn run_and_get_output<F: Fn()>(action: F) -> Vec<Value> {
         .collect()
 }
 
+// Keys of a json record in the order they're written, which `Value` doesn't keep
+fn record_keys(line: &str) -> Vec<String> {
+    struct Keys(Vec<String>);
+
+    impl<'de> serde::Deserialize<'de> for Keys {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct KeysVisitor;
+
+            impl<'de> serde::de::Visitor<'de> for KeysVisitor {
+                type Value = Keys;
+
+                fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    formatter.write_str("a json object")
+                }
+
+                fn visit_map<A: serde::de::MapAccess<'de>>(
+                    self,
+                    mut map: A,
+                ) -> Result<Keys, A::Error> {
+                    let mut keys = Vec::new();
+                    while let Some((key, _)) = map.next_entry::<String, serde::de::IgnoredAny>()? {
+                        keys.push(key);
+                    }
+                    Ok(Keys(keys))
+                }
+            }
+
+            deserializer.deserialize_map(KeysVisitor)
+        }
+    }
+
+    serde_json::from_str::<Keys>(line).unwrap().0
+}
+
 // Instrumented code to be run to test the behaviour of the tracing instrumentation.
 fn test_action() {
     let a = 2;
@@ -72,6 +131,7 @@ fn each_line_has_the_mandatory_fields() {
         assert!(record.get("level").is_some());
         assert!(record.get("time").is_some());
         assert!(record.get("message").is_some());
+        assert_eq!(record["custom_field"], "custom_value");
     }
 }
 
@@ -90,15 +150,19 @@ fn encode_f64_as_numbers() {
     };
     let tracing_output = run_and_get_output(action);
 
-    for record in tracing_output {
-        if record
-            .get("msg")
-            .and_then(Value::as_str)
-            .map_or(false, |msg| msg.contains("testing f64"))
-        {
-            let observed_value = record.get("f64_field").and_then(|v| v.as_f64());
-            assert_some_eq!(observed_value, f64_value);
-        }
+    let records: Vec<&Value> = tracing_output
+        .iter()
+        .filter(|record| {
+            record
+                .get("message")
+                .and_then(Value::as_str)
+                .is_some_and(|msg| msg.contains("testing f64"))
+        })
+        .collect();
+    assert_eq!(records.len(), 1);
+    for record in records {
+        let observed_value = record.get("f64_field").and_then(|v| v.as_f64());
+        assert_some_eq!(observed_value, f64_value);
     }
 }
 
@@ -106,13 +170,1474 @@ fn encode_f64_as_numbers() {
 fn elapsed_milliseconds_are_present_on_exit_span() {
     let tracing_output = run_and_get_output(test_action);
 
-    for record in tracing_output {
-        if record
-            .get("msg")
-            .and_then(Value::as_str)
-            .map_or(false, |msg| msg.ends_with("END]"))
-        {
-            assert!(record.get("elapsed_milliseconds").is_some());
+    let records: Vec<&Value> = tracing_output
+        .iter()
+        .filter(|record| {
+            record
+                .get("message")
+                .and_then(Value::as_str)
+                .is_some_and(|msg| msg.ends_with("END]"))
+        })
+        .collect();
+    assert!(!records.is_empty());
+    for record in records {
+        assert!(record.get("elapsed_milliseconds").is_some());
+    }
+}
+
+#[test]
+fn span_events_write_start_and_end_records() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .span_events(SpanEvents::NewAndClose)
+                .buffer(buffer)
+                .clone()
+        },
+        test_action,
+    );
+    let messages: Vec<String> = parse_output(&output)
+        .iter()
+        .map(|record| record["message"].as_str().unwrap().to_string())
+        .collect();
+
+    assert_eq!(
+        messages,
+        [
+            "shaving_yaks [START]",
+            "pre-shaving yaks",
+            "inner shaving [START]",
+            "shaving yaks",
+            "inner shaving [END]",
+            "shaving_yaks [END]",
+        ]
+    );
+}
+
+#[test]
+fn span_end_record_has_timings_and_span_fields() {
+    let output = run_and_get_output(test_action);
+    let end = output
+        .iter()
+        .find(|record| record["message"] == "inner shaving [END]")
+        .unwrap();
+
+    assert_eq!(end["span"], "shaving_yaks::inner shaving");
+    assert_eq!(end["a"], 2);
+    assert_eq!(end["b"], 3);
+    for key in [
+        "elapsed_milliseconds",
+        "busy_milliseconds",
+        "idle_milliseconds",
+    ] {
+        assert!(end[key].as_f64().is_some());
+    }
+}
+
+#[test]
+fn logfmt_quotes_and_escapes_values() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .logfmt()
+                .message_key("msg")
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            let _span = span!(Level::INFO, "request", user = "jack clayton").entered();
+            info!(
+                plain = "value",
+                quoted = "name=\"traceon\"",
+                lines = "first\nsecond",
+                empty = "",
+                number = 5,
+                "logged in"
+            );
+        },
+    );
+    let line = output.lines().next().unwrap();
+
+    assert!(line.starts_with("time="));
+    assert_eq!(output.lines().count(), 1);
+    for pair in [
+        " level=INFO",
+        " msg=\"logged in\"",
+        " plain=value",
+        r#" quoted="name=\"traceon\"""#,
+        r#" lines="first\nsecond""#,
+        r#" empty="""#,
+        " number=5",
+        " user=\"jack clayton\"",
+        " span=request",
+    ] {
+        assert!(line.contains(pair), "{pair} not found in {line}");
+    }
+}
+
+/// Writer that signals when each write starts, and then waits for `gate` to be unlocked
+struct GatedWriter {
+    gate: Arc<Mutex<()>>,
+    started: mpsc::Sender<()>,
+    buffer: InMemoryBuffer,
+}
+
+impl Write for GatedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.started.send(()).unwrap();
+        let _gate = self.gate.lock().unwrap();
+        self.buffer.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Log 10 events while the writer thread is stuck on the first, then one more after it has emptied the queue
+fn run_non_blocking_and_get_output(queue_full: QueueFull) -> Vec<Value> {
+    let buffer = InMemoryBuffer::default();
+    let gate = Arc::new(Mutex::new(()));
+    let (started, wait_for_write) = mpsc::channel();
+    let writer = GatedWriter {
+        gate: gate.clone(),
+        started,
+        buffer: buffer.clone(),
+    };
+    let (writer, guard) = NonBlocking::new(writer, 2, queue_full);
+    let subscriber =
+        Registry::default().with(traceon::builder().json().non_blocking(writer).clone());
+
+    tracing::subscriber::with_default(subscriber, || {
+        let closed_gate = gate.lock().unwrap();
+        info!(number = 1);
+        wait_for_write.recv().unwrap();
+        for number in 2..=10 {
+            info!(number);
+        }
+        drop(closed_gate);
+        // Both queued records have been taken from the queue once the third write starts
+        wait_for_write.recv().unwrap();
+        wait_for_write.recv().unwrap();
+        info!(number = 11);
+    });
+    assert_eq!(guard.dropped(), 7);
+    drop(guard);
+
+    let output = buffer.lock().unwrap().to_vec();
+    parse_output(&String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn non_blocking_drop_newest_keeps_queued_records() {
+    let output = run_non_blocking_and_get_output(QueueFull::DropNewest);
+    let numbers: Vec<&Value> = output.iter().map(|record| &record["number"]).collect();
+
+    assert_eq!(
+        numbers,
+        [&json!(1), &json!(2), &json!(3), &json!(11), &Value::Null]
+    );
+    assert_eq!(output[4]["level"], "WARN");
+    assert_eq!(output[4]["dropped_count"], 7);
+}
+
+#[test]
+fn non_blocking_drop_oldest_keeps_latest_records() {
+    let output = run_non_blocking_and_get_output(QueueFull::DropOldest);
+    let numbers: Vec<&Value> = output.iter().map(|record| &record["number"]).collect();
+
+    assert_eq!(
+        numbers,
+        [&json!(1), &json!(9), &json!(10), &json!(11), &Value::Null]
+    );
+    assert_eq!(output[4]["dropped_count"], 7);
+}
+
+#[test]
+fn non_blocking_reports_drops_after_the_last_record_when_the_guard_is_dropped() {
+    let buffer = InMemoryBuffer::default();
+    let gate = Arc::new(Mutex::new(()));
+    let (started, wait_for_write) = mpsc::channel();
+    let writer = GatedWriter {
+        gate: gate.clone(),
+        started,
+        buffer: buffer.clone(),
+    };
+    let (writer, guard) = NonBlocking::new(writer, 2, QueueFull::DropNewest);
+    let subscriber =
+        Registry::default().with(traceon::builder().json().non_blocking(writer).clone());
+
+    tracing::subscriber::with_default(subscriber, || {
+        let closed_gate = gate.lock().unwrap();
+        info!(number = 1);
+        wait_for_write.recv().unwrap();
+        for number in 2..=10 {
+            info!(number);
+        }
+        drop(closed_gate);
+    });
+    // Nothing is logged after the drops, so they're reported on shutdown
+    drop(guard);
+
+    let output = buffer.lock().unwrap().to_vec();
+    let output = parse_output(&String::from_utf8(output).unwrap());
+    let numbers: Vec<&Value> = output.iter().map(|record| &record["number"]).collect();
+    assert_eq!(numbers, [&json!(1), &json!(2), &json!(3), &Value::Null]);
+    assert_eq!(output[3]["level"], "WARN");
+    assert_eq!(output[3]["dropped_count"], 7);
+}
+
+// Empty directory in the system temp directory that is unique to this test
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("traceon-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn rolling_file_rotates_on_size_from_many_threads() {
+    let dir = temp_dir("rolling-size");
+    let traceon = traceon::builder()
+        .json()
+        .rolling_file(
+            RollingFile::new(&dir, "app.log")
+                .max_size(1000)
+                .max_files(3),
+        )
+        .unwrap()
+        .clone();
+    let subscriber = Registry::default().with(traceon);
+
+    tracing::subscriber::with_default(subscriber, || {
+        let dispatch = tracing::dispatcher::Dispatch::default();
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let dispatch = dispatch.clone();
+                scope.spawn(move || {
+                    tracing::dispatcher::with_default(&dispatch, || {
+                        for number in 0..50 {
+                            info!(thread, number, "writing from many threads");
+                        }
+                    })
+                });
+            }
+        });
+    });
+
+    let mut files: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    // The current file plus the 3 most recently rotated files
+    assert_eq!(files.len(), 4);
+    assert!(files.contains(&"app.log".to_string()));
+    for file in files {
+        let contents = std::fs::read_to_string(dir.join(&file)).unwrap();
+        assert!(contents.len() <= 1000);
+        // Every record is whole
+        assert!(!parse_output(&contents).is_empty());
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rolling_file_keeps_writing_when_old_files_cant_be_removed() {
+    let dir = temp_dir("rolling-prune-error");
+    // A directory that looks like an old rotated file can't be removed like one
+    std::fs::create_dir_all(dir.join("app.log.old").join("nested")).unwrap();
+    let mut writer = RollingFile::new(&dir, "app.log")
+        .max_size(10)
+        .max_files(1)
+        .open()
+        .unwrap();
+
+    for number in 0..3 {
+        writer
+            .write_all(format!("{{\"number\":{number}}}\n").as_bytes())
+            .unwrap();
+    }
+
+    let current = std::fs::read_to_string(dir.join("app.log")).unwrap();
+    assert_eq!(parse_output(&current), [json!({ "number": 2 })]);
+    assert!(dir.join("app.log.old").is_dir());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn rolling_file_compresses_rotated_files() {
+    use std::io::Read;
+
+    let dir = temp_dir("rolling-gzip");
+    let traceon = traceon::builder()
+        .json()
+        .rolling_file(RollingFile::new(&dir, "app.log").max_size(200).gzip())
+        .unwrap()
+        .clone();
+    tracing::subscriber::with_default(Registry::default().with(traceon), || {
+        for number in 0..5 {
+            info!(number, "compress me");
+        }
+    });
+
+    let compressed: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "gz"))
+        .collect();
+    assert!(!compressed.is_empty());
+    for path in compressed {
+        let mut contents = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(path).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(parse_output(&contents)[0]["message"], "compress me");
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn routes_pick_writer_and_format_by_level_and_target() {
+    let errors = InMemoryBuffer::default();
+    let dependency = InMemoryBuffer::default();
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .route(Route::buffer(errors.clone()).level(Level::WARN))
+                .route(
+                    Route::buffer(dependency.clone())
+                        .target("hyper")
+                        .format(Format::Logfmt),
+                )
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            tracing::error!(target: "hyper::client", "error from anywhere");
+            info!(target: "hyper::client", "info from hyper");
+            info!(target: "hyperactive", "info from another crate");
+            info!("info from here");
+        },
+    );
+    let errors = String::from_utf8(errors.lock().unwrap().to_vec()).unwrap();
+    let dependency = String::from_utf8(dependency.lock().unwrap().to_vec()).unwrap();
+
+    let errors = parse_output(&errors);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["message"], "error from anywhere");
+
+    assert_eq!(dependency.lines().count(), 1);
+    assert!(dependency.contains(r#" message="info from hyper""#));
+
+    let messages: Vec<Value> = parse_output(&output)
+        .into_iter()
+        .map(|record| record["message"].clone())
+        .collect();
+    assert_eq!(messages, ["info from another crate", "info from here"]);
+}
+
+#[test]
+fn redacted_fields_are_masked_removed_or_partially_kept() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .redact(&["password", "Authorization"], Redact::Mask)
+                .redact(&["*token*"], Redact::Remove)
+                .redact(&["card_?umber"], Redact::KeepLast(4))
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            let _span = span!(Level::INFO, "login", user = "jack", password = "hunter2").entered();
+            let _inner = span!(Level::INFO, "inner", card_number = 4111111111111111u64).entered();
+            let authorization = Some("Bearer abc");
+            info!(
+                ?authorization,
+                session_token = "abc123",
+                pin = "12",
+                "logged in"
+            );
+        },
+    );
+    let record = &parse_output(&output)[0];
+
+    assert_eq!(record["user"], "jack");
+    assert_eq!(record["password"], "[REDACTED]");
+    assert_eq!(record["authorization"], "[REDACTED]");
+    assert!(record.get("session_token").is_none());
+    assert_eq!(record["card_number"], "****1111");
+    assert_eq!(record["pin"], "12");
+    assert!(!output.contains("hunter2"));
+    assert!(!output.contains("abc"));
+}
+
+#[test]
+fn nested_json_keeps_each_spans_own_fields_from_root_to_leaf() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| traceon::builder().nested_json().buffer(buffer).clone(),
+        || {
+            let outer = span!(Level::INFO, "request", id = 5, user = "jack");
+            let _outer = outer.enter();
+            let inner = span!(Level::INFO, "query", id = 10, rows = tracing::field::Empty);
+            let _inner = inner.enter();
+            inner.record("rows", 2);
+            info!(cached = false, "query complete");
+        },
+    );
+    let record = &parse_output(&output)[0];
+
+    assert_eq!(record["message"], "query complete");
+    assert_eq!(record["fields"], json!({ "cached": false }));
+    assert_eq!(
+        record["spans"],
+        json!([
+            { "name": "request", "id": 5, "user": "jack" },
+            { "name": "query", "id": 10, "rows": 2 }
+        ])
+    );
+    // Span fields aren't flattened onto the record
+    assert!(record.get("id").is_none());
+    assert!(record.get("span").is_none());
+}
+
+#[test]
+fn child_spans_join_or_overwrite_the_fields_of_their_parents() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .time(TimeFormat::None)
+                .span(SpanFormat::Join(">"))
+                .join_fields(JoinFields::Some("::", &["path"]))
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            let outer = span!(
+                Level::INFO,
+                "request",
+                path = "api",
+                id = 1,
+                status = tracing::field::Empty
+            );
+            let _outer = outer.enter();
+            let inner = span!(Level::INFO, "query", path = "users", id = 2);
+            let _inner = inner.enter();
+            info!("before record");
+            // Children keep the fields their parent had when they were created
+            outer.record("path", "v2");
+            outer.record("status", 200);
+            inner.record("path", "active");
+            info!("after record");
+            drop(_inner);
+            info!("in parent");
+        },
+    );
+    let records = parse_output(&output);
+
+    assert_eq!(records[0]["span"], "request>query");
+    assert_eq!(records[0]["path"], "api::users");
+    assert_eq!(records[0]["id"], 2);
+    assert!(records[0].get("status").is_none());
+    assert_eq!(records[1]["path"], "api::users::active");
+    assert!(records[1].get("status").is_none());
+    assert_eq!(records[2]["span"], "request");
+    assert_eq!(records[2]["path"], "api::v2");
+    assert_eq!(records[2]["status"], 200);
+    assert_eq!(records[2]["id"], 1);
+}
+
+#[test]
+fn structured_values_are_nested_json_and_pretty_sub_trees() {
+    let action = || {
+        let _span = span!(Level::INFO, "request", sizes = ?structured([10, 15, 20])).entered();
+        let user = json!({ "name": "jack", "roles": ["admin"] });
+        let wrapped = Some(structured(1));
+        info!(user = ?structured(&user), ?wrapped, "logged in");
+    };
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| traceon::builder().json().buffer(buffer).clone(),
+        action,
+    );
+    let record = &parse_output(&output)[0];
+    assert_eq!(record["sizes"], json!([10, 15, 20]));
+    assert_eq!(
+        record["user"],
+        json!({ "name": "jack", "roles": ["admin"] })
+    );
+    // Nested inside another type's `Debug` output it's formatted as a string
+    assert_eq!(record["wrapped"], "Some(1)");
+
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .time(TimeFormat::None)
+                .span(SpanFormat::None)
+                .buffer(buffer)
+                .clone()
+        },
+        action,
+    );
+    let lines: Vec<&str> = output
+        .lines()
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "    user:",
+            "        name: jack",
+            "        roles:",
+            "            - admin",
+            "    wrapped: Some(1)",
+            "    sizes:",
+            "        - 10",
+            "        - 15",
+            "        - 20",
+        ]
+    );
+}
+
+#[derive(Debug)]
+struct ConfigError {
+    source: std::io::Error,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to load config")
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn config_error() -> ConfigError {
+    ConfigError {
+        source: std::io::Error::other("permission denied"),
+    }
+}
+
+#[test]
+fn errors_keep_their_source_chain() {
+    let action = || {
+        let error = config_error();
+        tracing::error!(error = &error as &dyn std::error::Error, "startup failed");
+    };
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| traceon::builder().json().buffer(buffer).clone(),
+        action,
+    );
+    let record = &parse_output(&output)[0];
+    assert_eq!(
+        record["error"],
+        json!({
+            "message": "failed to load config",
+            "type": "ConfigError",
+            "sources": ["permission denied"]
+        })
+    );
+
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .time(TimeFormat::None)
+                .buffer(buffer)
+                .clone()
+        },
+        action,
+    );
+    let lines: Vec<&str> = output.lines().skip(1).collect();
+    assert_eq!(
+        lines,
+        [
+            "    error: failed to load config (ConfigError)",
+            "        caused by:",
+            "            - permission denied",
+            "",
+        ]
+    );
+}
+
+#[cfg(feature = "tracing-error")]
+#[test]
+fn errors_include_span_trace() {
+    use tracing_error::{ErrorLayer, InstrumentError};
+
+    let buffer = InMemoryBuffer::default();
+    let subscriber = Registry::default()
+        .with(ErrorLayer::default())
+        .with(traceon::builder().json().buffer(buffer.clone()).clone());
+    tracing::subscriber::with_default(subscriber, || {
+        let error = {
+            let _span = span!(Level::INFO, "load_config", path = "app.toml").entered();
+            config_error().in_current_span()
+        };
+        tracing::error!(error = &error as &dyn std::error::Error, "startup failed");
+    });
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let record = &parse_output(&output)[0];
+
+    assert_eq!(record["error"]["message"], "failed to load config");
+    let span_trace = record["error"]["span_trace"].as_array().unwrap();
+    assert_eq!(span_trace.len(), 1);
+    assert_eq!(span_trace[0]["name"], "e2e::load_config");
+    assert_eq!(span_trace[0]["fields"], "path=\"app.toml\"");
+}
+
+#[cfg(feature = "opentelemetry")]
+#[test]
+fn opentelemetry_ids_are_written_with_the_case_setting() {
+    use opentelemetry::trace::{TraceContextExt, TracerProvider};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder().build();
+    let telemetry = tracing_opentelemetry::layer().with_tracer(provider.tracer("e2e"));
+    let buffer = InMemoryBuffer::default();
+    let subscriber = Registry::default().with(telemetry).with(
+        traceon::builder()
+            .json()
+            .case(traceon::Case::Camel)
+            .buffer(buffer.clone())
+            .clone(),
+    );
+    let (trace_id, span_id) = tracing::subscriber::with_default(subscriber, || {
+        let root = span!(Level::INFO, "root");
+        let _root = root.enter();
+        info!("before the sampling decision");
+        let child = span!(Level::INFO, "child");
+        let _child = child.enter();
+        info!("in child");
+        let context = child.context();
+        let span_context = context.span().span_context().clone();
+        (
+            span_context.trace_id().to_string(),
+            span_context.span_id().to_string(),
+        )
+    });
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let records = parse_output(&output);
+
+    assert_eq!(records[0]["traceId"], trace_id);
+    assert!(records[0].get("traceFlags").is_none());
+    assert_eq!(records[1]["traceId"], trace_id);
+    assert_eq!(records[1]["spanId"], span_id);
+    assert_eq!(records[1]["traceFlags"], "01");
+    assert_ne!(records[0]["spanId"], records[1]["spanId"]);
+}
+
+#[test]
+fn trace_ids_are_shared_by_the_trace_and_continue_a_traceparent() {
+    let traceparent = Arc::new(Mutex::new(None));
+    let current = traceparent.clone();
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| traceon::builder().json().trace_ids().buffer(buffer).clone(),
+        move || {
+            let root = span!(Level::INFO, "root");
+            let _root = root.enter();
+            info!("in root");
+            let child = span!(Level::INFO, "child");
+            let _child = child.enter();
+            info!("in child");
+            *current.lock().unwrap() = TraceParent::current();
+
+            let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+            let _remote =
+                span!(parent: None, Level::INFO, "remote", traceparent = header).entered();
+            info!("continued");
+        },
+    );
+    let records = parse_output(&output);
+    let (root, child, remote) = (&records[0], &records[1], &records[2]);
+
+    let trace_id = root["trace_id"].as_str().unwrap();
+    assert_eq!(trace_id.len(), 32);
+    assert_eq!(root["span_id"].as_str().unwrap().len(), 16);
+    assert!(root.get("parent_span_id").is_none());
+    assert_eq!(child["trace_id"], trace_id);
+    assert_eq!(child["parent_span_id"], root["span_id"]);
+    assert_ne!(child["span_id"], root["span_id"]);
+
+    let current = traceparent.lock().unwrap().unwrap();
+    assert_eq!(
+        current.to_string(),
+        format!("00-{trace_id}-{}-01", child["span_id"].as_str().unwrap())
+    );
+
+    assert_eq!(remote["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(remote["parent_span_id"], "00f067aa0ba902b7");
+}
+
+#[test]
+fn traceparent_parses_and_formats_w3c_strings() {
+    let value = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00";
+    let traceparent = TraceParent::parse(value).unwrap();
+    assert_eq!(traceparent.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+    assert_eq!(traceparent.span_id, 0x00f067aa0ba902b7);
+    assert!(!traceparent.sampled);
+    assert_eq!(traceparent.to_string(), value);
+
+    for invalid in [
+        "",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+    ] {
+        assert_eq!(TraceParent::parse(invalid), None, "{invalid}");
+    }
+}
+
+#[test]
+fn filter_directives_and_target_levels_from_the_builder() {
+    let buffer = InMemoryBuffer::default();
+    let guard = traceon::builder()
+        .json()
+        .filter("warn,app::db=error")
+        .target_level("app", Level::DEBUG)
+        .ignore_filter_env()
+        .buffer(buffer.clone())
+        .on_thread();
+    info!(target: "other", "filtered by the default directive");
+    tracing::warn!(target: "other", "kept by the default directive");
+    tracing::debug!(target: "app::http", "kept by the target level");
+    tracing::warn!(target: "app::db", "filtered by the more specific directive");
+    drop(guard);
+
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let messages: Vec<Value> = parse_output(&output)
+        .into_iter()
+        .map(|record| record["message"].clone())
+        .collect();
+    assert_eq!(
+        messages,
+        ["kept by the default directive", "kept by the target level"]
+    );
+
+    let error = traceon::builder()
+        .filter("info,app=loud")
+        .ignore_filter_env()
+        .try_on()
+        .unwrap_err();
+    let traceon::OnError::Filter(error) = error else {
+        panic!("expected a filter error, got {error}");
+    };
+    assert_eq!(error.variable, None);
+    assert_eq!(error.directives, "info,app=loud");
+}
+
+#[test]
+fn dedup_counts_repeats_and_writes_a_follow_up_when_the_window_closes() {
+    let action = || {
+        for attempt in 0..3 {
+            tracing::warn!(attempt, "retrying");
         }
+        info!("different message");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        tracing::warn!(attempt = 3, "retrying");
+    };
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .dedup(Dedup::new(std::time::Duration::from_millis(50)))
+                .buffer(buffer)
+                .clone()
+        },
+        action,
+    );
+    let records = parse_output(&output);
+    let summary: Vec<(Value, Value, Value)> = records
+        .iter()
+        .map(|record| {
+            (
+                record["message"].clone(),
+                record["attempt"].clone(),
+                record["suppressed_count"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (json!("retrying"), json!(0), Value::Null),
+            (json!("different message"), Value::Null, Value::Null),
+            (json!("retrying"), json!(2), json!(2)),
+            (json!("retrying"), json!(3), Value::Null),
+        ]
+    );
+
+    // Repeats are compared on the selected fields, and the follow-up works in pretty output
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .time(TimeFormat::None)
+                .dedup(Dedup::new(std::time::Duration::from_millis(50)).fields(&["url"]))
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            for url in ["/a", "/a", "/b", "/a"] {
+                tracing::warn!(url, "request failed");
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            info!("done");
+        },
+    );
+    // Skip the headlines
+    let fields: Vec<&str> = output
+        .lines()
+        .filter(|line| line.starts_with("    "))
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            "    url: /a",
+            "    url: /b",
+            "    url:              /a",
+            "    suppressed_count: 2",
+        ]
+    );
+    assert!(output.contains("INFO done"));
+}
+
+#[test]
+fn dedup_writes_the_follow_up_for_open_windows_when_the_layer_is_dropped() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .dedup(Dedup::new(std::time::Duration::from_secs(60)))
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            for attempt in 0..3 {
+                tracing::warn!(attempt, "retrying");
+            }
+            info!("written once");
+        },
+    );
+    // No event arrives after the window, the follow-up is written when the subscriber is dropped
+    let records = parse_output(&output);
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[2]["message"], "retrying");
+    assert_eq!(records[2]["attempt"], 2);
+    assert_eq!(records[2]["suppressed_count"], 2);
+}
+
+#[test]
+fn default_fields_follow_case_and_have_the_lowest_priority() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .case(traceon::Case::Camel)
+                .default_fields([
+                    ("service_name", json!("checkout")),
+                    ("region", json!({ "name": "us-east-1" })),
+                    ("user", json!("anonymous")),
+                    ("request_id", json!("none")),
+                ])
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            let span = span!(Level::INFO, "request", request_id = "abc").entered();
+            info!(user = "jack", "logged in");
+            drop(span);
+            info!("outside of any span");
+        },
+    );
+    let records = parse_output(&output);
+
+    assert_eq!(records[0]["serviceName"], "checkout");
+    assert_eq!(records[0]["region"], json!({ "name": "us-east-1" }));
+    assert_eq!(records[0]["user"], "jack");
+    assert_eq!(records[0]["requestId"], "abc");
+    assert_eq!(
+        output.lines().next().unwrap().matches("requestId").count(),
+        1
+    );
+    assert_eq!(records[1]["serviceName"], "checkout");
+    assert_eq!(records[1]["user"], "anonymous");
+}
+
+#[test]
+fn colors_are_off_for_buffers_unless_forced_and_follow_the_theme() {
+    let action = || info!(count = 3, ok = true, name = "jack", "colored");
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .time(TimeFormat::None)
+                .buffer(buffer)
+                .clone()
+        },
+        action,
+    );
+    assert!(!output.contains('\x1b'));
+    assert!(output.starts_with("INFO colored\n"));
+
+    let theme = Theme {
+        info: Style::new().fg(Color::Cyan),
+        key: Style::new().bold(),
+        string: Style::new().fg(Color::Green),
+        ..Theme::default()
+    };
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .time(TimeFormat::None)
+                .color(ColorMode::Always)
+                .theme(theme)
+                .buffer(buffer)
+                .clone()
+        },
+        action,
+    );
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(
+        lines[0],
+        format!(
+            "{} {}",
+            Color::Cyan.paint("INFO"),
+            Color::Cyan.paint("colored")
+        )
+    );
+    // Keys are aligned on their length without the escape codes
+    assert_eq!(
+        lines[1],
+        format!(
+            "    {}: {}",
+            Style::new().bold().paint("count"),
+            theme.number.paint("3")
+        )
+    );
+    assert_eq!(
+        lines[2],
+        format!(
+            "    {}:    {}",
+            Style::new().bold().paint("ok"),
+            theme.boolean.paint("true")
+        )
+    );
+    assert_eq!(
+        lines[3],
+        format!(
+            "    {}:  {}",
+            Style::new().bold().paint("name"),
+            Color::Green.paint("jack")
+        )
+    );
+
+    // Json is only colored when asked for, and is valid json with the escape codes removed
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .color(ColorMode::Always)
+                .buffer(buffer)
+                .clone()
+        },
+        action,
+    );
+    assert!(!output.contains('\x1b'));
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .colored_json()
+                .color(ColorMode::Always)
+                .buffer(buffer)
+                .clone()
+        },
+        action,
+    );
+    assert!(output.contains(&Theme::default().key.paint("\"count\"").to_string()));
+    let mut plain = String::new();
+    let mut escape = false;
+    for ch in output.chars() {
+        match ch {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            ch if !escape => plain.push(ch),
+            _ => (),
+        }
+    }
+    let record: Value = serde_json::from_str(plain.trim()).unwrap();
+    assert_eq!(record["name"], "jack");
+    assert_eq!(record["count"], 3);
+}
+
+#[test]
+fn pretty_aligns_by_display_width_and_wraps_under_the_value() {
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .time(TimeFormat::None)
+                .wrap(Wrap::Width(40))
+                .pretty_json_strings()
+                .alternate_debug()
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            info!(
+                名前 = "ジャック",
+                id = 5,
+                note = "the quick brown fox jumps over the lazy dog twice",
+                lines = "first\nsecond",
+                body = r#"{"user":"jack"}"#,
+                point = ?Point { x: 1, y: 2 },
+                "layout"
+            );
+        },
+    );
+    let expected = [
+        "INFO layout",
+        "    名前:  ジャック",
+        "    id:    5",
+        "    note:  the quick brown fox jumps",
+        "           over the lazy dog twice",
+        "    lines: first",
+        "           second",
+        "    body:",
+        "        user: jack",
+        "    point: Point {",
+        "               x: 1,",
+        "               y: 2,",
+        "           }",
+    ];
+    assert_eq!(
+        output.lines().collect::<Vec<_>>()[..expected.len()],
+        expected
+    );
+}
+
+#[test]
+fn alternate_debug_only_spreads_pretty_output_over_lines() {
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .json()
+                .alternate_debug()
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            let span = span!(Level::INFO, "request", origin = ?Point { x: 0, y: 0 });
+            let _span = span.enter();
+            info!(point = ?Point { x: 1, y: 2 }, "moved");
+        },
+    );
+    assert_eq!(output.lines().count(), 1);
+    let record = &parse_output(&output)[0];
+    assert_eq!(record["point"], "Point { x: 1, y: 2 }");
+    assert_eq!(record["origin"], "Point { x: 0, y: 0 }");
+
+    // A json route from a pretty layer stays on one line too
+    let json_buffer = InMemoryBuffer::default();
+    let pretty = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .time(TimeFormat::None)
+                .alternate_debug()
+                .route(
+                    Route::buffer(json_buffer.clone())
+                        .level(Level::WARN)
+                        .format(Format::Json),
+                )
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            info!(point = ?Point { x: 1, y: 2 }, "moved");
+            tracing::warn!(point = ?Point { x: 3, y: 4 }, "out of bounds");
+        },
+    );
+    assert!(pretty.contains("    point: Point {\n"));
+    let json = String::from_utf8(json_buffer.lock().unwrap().to_vec()).unwrap();
+    assert_eq!(json.lines().count(), 1);
+    assert_eq!(parse_output(&json)[0]["point"], "Point { x: 3, y: 4 }");
+}
+
+#[test]
+fn fields_are_written_in_a_stable_order_with_pinned_keys_first() {
+    let action = || {
+        let _outer = span!(Level::INFO, "outer", zone = "b", tenant = "acme").entered();
+        let _inner = span!(Level::INFO, "inner", request_id = "abc").entered();
+        info!(zebra = 1, apple = 2, user = "jack", "ordered");
+    };
+    let output = |format: Format, field_order: &'static [&'static str]| {
+        run_with_layer_and_get_raw_output(
+            |buffer| {
+                traceon::builder()
+                    .format(format)
+                    .time(TimeFormat::None)
+                    .span(SpanFormat::None)
+                    .default_fields([("service", "checkout")])
+                    .field_order(field_order)
+                    .buffer(buffer)
+                    .clone()
+            },
+            action,
+        )
+    };
+
+    // Built-in keys, event fields as declared, span fields from root to leaf, then default fields
+    assert_eq!(
+        output(Format::Json, &[]).trim(),
+        r#"{"level":"INFO","message":"ordered","zebra":1,"apple":2,"user":"jack","zone":"b","tenant":"acme","request_id":"abc","service":"checkout"}"#
+    );
+    assert!(
+        output(Format::NestedJson, &[]).contains(r#""fields":{"zebra":1,"apple":2,"user":"jack"}"#)
+    );
+
+    let pinned = &["request_id", "user"];
+    assert_eq!(
+        output(Format::Json, pinned).trim(),
+        r#"{"request_id":"abc","user":"jack","level":"INFO","message":"ordered","zebra":1,"apple":2,"zone":"b","tenant":"acme","service":"checkout"}"#
+    );
+    assert_eq!(
+        output(Format::Pretty, pinned).lines().collect::<Vec<_>>(),
+        [
+            "INFO ordered",
+            "    request_id: abc",
+            "    user:       jack",
+            "    zebra:      1",
+            "    apple:      2",
+            "    zone:       b",
+            "    tenant:     acme",
+            "    service:    checkout",
+            "",
+        ]
+    );
+}
+
+#[test]
+fn built_in_keys_are_renamed_in_every_format() {
+    let action = || {
+        let _span = span!(Level::INFO, "request", user = "jack").entered();
+        info!("renamed");
+    };
+    let output = |format: Format, case: Case| {
+        run_with_layer_and_get_raw_output(
+            |buffer| {
+                traceon::builder()
+                    .format(format)
+                    .case(case)
+                    .time(TimeFormat::CustomFormat("12:00"))
+                    .module()
+                    .file()
+                    .keys(Keys {
+                        time: "@timestamp",
+                        level: "severity",
+                        message: "msg",
+                        module: "logger",
+                        file: "source",
+                        span: "operation",
+                        fields: "attributes",
+                        spans: "trace",
+                        name: "operation_name",
+                    })
+                    .buffer(buffer)
+                    .clone()
+            },
+            action,
+        )
+    };
+
+    let json = &parse_output(&output(Format::Json, Case::None))[0];
+    assert_eq!(json["@timestamp"], "12:00");
+    assert_eq!(json["severity"], "INFO");
+    assert_eq!(json["msg"], "renamed");
+    assert_eq!(json["logger"], "e2e");
+    assert!(
+        json["source"]
+            .as_str()
+            .unwrap()
+            .starts_with("tests/e2e.rs:")
+    );
+    assert_eq!(json["operation"], "request");
+    for key in ["time", "level", "message", "module", "file", "span"] {
+        assert!(json.get(key).is_none(), "{key} wasn't renamed");
+    }
+
+    let nested = &parse_output(&output(Format::NestedJson, Case::None))[0];
+    assert_eq!(
+        nested["trace"],
+        json!([{ "operation_name": "request", "user": "jack" }])
+    );
+
+    // Renamed keys follow the case setting like any other key
+    let pascal = &parse_output(&output(Format::Json, Case::Pascal))[0];
+    assert_eq!(pascal["Severity"], "INFO");
+    assert_eq!(pascal["Operation"], "request");
+
+    let pretty = output(Format::Pretty, Case::None);
+    let lines: Vec<&str> = pretty.lines().collect();
+    assert_eq!(lines[0], "12:00 INFO renamed");
+    assert_eq!(lines[1], "    logger:    e2e");
+    assert!(lines[2].starts_with("    source:    tests/e2e.rs:"));
+    assert_eq!(lines[3], "    operation: request");
+    assert_eq!(lines[4], "    user:      jack");
+}
+
+#[test]
+fn google_cloud_writes_severity_source_location_and_trace() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .google_cloud("my-project")
+                .trace_ids()
+                .case(Case::Pascal)
+                .buffer(buffer)
+                .clone()
+        },
+        || {
+            tracing::trace!("untraced");
+            let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+            let _span = span!(Level::INFO, "request", traceparent = header).entered();
+            tracing::warn!(user_name = "jack", "slow request");
+        },
+    );
+    let records = parse_output(&output);
+    let (untraced, warning) = (&records[0], &records[1]);
+
+    assert_eq!(untraced["severity"], "DEBUG");
+    assert!(untraced.get("logging.googleapis.com/trace").is_none());
+    assert!(
+        chrono::DateTime::parse_from_rfc3339(untraced["time"].as_str().unwrap()).is_ok(),
+        "time isn't RFC 3339"
+    );
+
+    assert_eq!(warning["severity"], "WARNING");
+    assert_eq!(warning["message"], "slow request");
+    let location = &warning["logging.googleapis.com/sourceLocation"];
+    assert_eq!(location["file"], "tests/e2e.rs");
+    assert!(location["line"].as_str().unwrap().parse::<u32>().is_ok());
+    assert_eq!(location["function"], "e2e");
+    assert_eq!(
+        warning["logging.googleapis.com/trace"],
+        "projects/my-project/traces/4bf92f3577b34da6a3ce929d0e0e4736"
+    );
+    assert_eq!(
+        warning["logging.googleapis.com/spanId"]
+            .as_str()
+            .unwrap()
+            .len(),
+        16
+    );
+    assert_eq!(warning["ParentSpanId"], "00f067aa0ba902b7");
+    // Only the other fields follow the case setting
+    assert_eq!(warning["UserName"], "jack");
+    assert_eq!(warning["Span"], "request");
+}
+
+#[test]
+fn ecs_nests_built_in_fields_and_puts_other_fields_under_labels() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| traceon::builder().ecs().trace_ids().buffer(buffer).clone(),
+        || {
+            let _outer = span!(Level::INFO, "request", tenant = "acme").entered();
+            let _inner = span!(Level::INFO, "query").entered();
+            let user = json!({ "name": "jack" });
+            tracing::warn!(
+                http.request.method = "GET",
+                user = ?structured(&user),
+                rows = 2,
+                "slow query"
+            );
+        },
+    );
+    let record = &parse_output(&output)[0];
+
+    assert!(
+        chrono::DateTime::parse_from_rfc3339(record["@timestamp"].as_str().unwrap()).is_ok(),
+        "@timestamp isn't RFC 3339"
+    );
+    assert_eq!(record["message"], "slow query");
+    assert_eq!(record["ecs"], json!({ "version": "8.11.0" }));
+    assert_eq!(record["log"]["level"], "warn");
+    assert_eq!(record["log"]["logger"], "e2e");
+    assert_eq!(record["log"]["origin"]["file"]["name"], "tests/e2e.rs");
+    assert!(record["log"]["origin"]["file"]["line"].is_u64());
+    assert_eq!(record["span"]["name"], "request::query");
+    assert_eq!(record["span"]["id"].as_str().unwrap().len(), 16);
+    assert_eq!(record["trace"]["id"].as_str().unwrap().len(), 32);
+    assert_eq!(record["http"], json!({ "request": { "method": "GET" } }));
+    assert_eq!(record["user"], json!({ "name": "jack" }));
+    assert_eq!(record["labels"]["rows"], 2);
+    assert_eq!(record["labels"]["tenant"], "acme");
+    assert_eq!(
+        record["labels"]["parent_span_id"].as_str().unwrap().len(),
+        16
+    );
+}
+
+#[test]
+fn ecs_keeps_the_namespaces_of_dotted_keys_with_a_case() {
+    let output = run_with_layer_and_get_raw_output(
+        |buffer| {
+            traceon::builder()
+                .ecs()
+                .case(Case::Snake)
+                .buffer(buffer)
+                .clone()
+        },
+        || tracing::warn!(http.request.method = "GET", userName = "jack", "slow query"),
+    );
+    let record = &parse_output(&output)[0];
+
+    assert_eq!(record["http"], json!({ "request": { "method": "GET" } }));
+    assert_eq!(record["labels"], json!({ "user_name": "jack" }));
+}
+
+#[test]
+fn keys_convert_to_every_case_with_unicode_and_exemptions() {
+    let output = |case: Case| {
+        let output = run_with_layer_and_get_raw_output(
+            |buffer| {
+                traceon::builder()
+                    .json()
+                    .time(TimeFormat::None)
+                    .level(traceon::LevelFormat::None)
+                    .span(SpanFormat::None)
+                    .case(case)
+                    .case_exempt(&["X-Request-Id"])
+                    .buffer(buffer)
+                    .clone()
+            },
+            || {
+                info!(
+                    "X-Request-Id" = 1,
+                    HTTPServer = 2,
+                    field2Name = 3,
+                    émileZola = 4,
+                    straße_größe = 5,
+                    名前 = 6,
+                    http.requestMethod = 7,
+                    "converted"
+                )
+            },
+        );
+        record_keys(output.lines().next().unwrap())
+            .into_iter()
+            .skip(1)
+            .collect::<Vec<_>>()
+    };
+
+    // Each part of a dotted key is converted on its own
+    let cases = [
+        (
+            Case::None,
+            [
+                "X-Request-Id",
+                "HTTPServer",
+                "field2Name",
+                "émileZola",
+                "straße_größe",
+                "名前",
+                "http.requestMethod",
+            ],
+        ),
+        (
+            Case::Camel,
+            [
+                "X-Request-Id",
+                "httpServer",
+                "field2Name",
+                "émileZola",
+                "straßeGröße",
+                "名前",
+                "http.requestMethod",
+            ],
+        ),
+        (
+            Case::Pascal,
+            [
+                "X-Request-Id",
+                "HttpServer",
+                "Field2Name",
+                "ÉmileZola",
+                "StraßeGröße",
+                "名前",
+                "Http.RequestMethod",
+            ],
+        ),
+        (
+            Case::Snake,
+            [
+                "X-Request-Id",
+                "http_server",
+                "field2_name",
+                "émile_zola",
+                "straße_größe",
+                "名前",
+                "http.request_method",
+            ],
+        ),
+        (
+            Case::ScreamingSnake,
+            [
+                "X-Request-Id",
+                "HTTP_SERVER",
+                "FIELD2_NAME",
+                "ÉMILE_ZOLA",
+                "STRASSE_GRÖSSE",
+                "名前",
+                "HTTP.REQUEST_METHOD",
+            ],
+        ),
+        (
+            Case::Kebab,
+            [
+                "X-Request-Id",
+                "http-server",
+                "field2-name",
+                "émile-zola",
+                "straße-größe",
+                "名前",
+                "http.request-method",
+            ],
+        ),
+        (
+            Case::Dot,
+            [
+                "X-Request-Id",
+                "http.server",
+                "field2.name",
+                "émile.zola",
+                "straße.größe",
+                "名前",
+                "http.request.method",
+            ],
+        ),
+    ];
+    for (case, keys) in cases {
+        assert_eq!(output(case), keys);
     }
 }
@@ -0,0 +1,169 @@
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry};
+
+// Environment variables are shared by the whole process, so everything is tested in a single test
+#[test]
+fn environment_variables_configure_the_builder() {
+    // SAFETY: this is the only test in this binary, so nothing else is reading the environment
+    unsafe {
+        std::env::set_var("TRACEON_FORMAT", "JSON");
+        std::env::set_var("TRACEON_MODULE", "1");
+        std::env::set_var("TRACEON_TIME", "none");
+        std::env::set_var("TRACEON_MESSAGE_KEY", "msg");
+        std::env::set_var("TRACEON_SPAN", "join:>");
+        std::env::set_var(
+            "TRACEPARENT",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        );
+    }
+
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    // Settings made after `from_env` override the environment
+    let traceon = traceon::from_env()
+        .unwrap()
+        .message_key("message")
+        .buffer(buffer.clone())
+        .clone();
+    tracing::subscriber::with_default(Registry::default().with(traceon), || {
+        let _outer = tracing::info_span!("outer").entered();
+        let _inner = tracing::info_span!("inner").entered();
+        tracing::info!("from the environment");
+    });
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let record: Value = serde_json::from_str(output.trim()).unwrap();
+
+    assert_eq!(record["message"], "from the environment");
+    assert_eq!(record["module"], "env");
+    assert_eq!(record["span"], "outer>inner");
+    assert!(record.get("time").is_none());
+    assert!(record.get("msg").is_none());
+
+    // Settings made before `env` are overridden by the environment
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let traceon = traceon::builder()
+        .logfmt()
+        .env()
+        .unwrap()
+        .buffer(buffer.clone())
+        .clone();
+    tracing::subscriber::with_default(Registry::default().with(traceon), || {
+        tracing::info!("from the environment");
+    });
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let record: Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(record["msg"], "from the environment");
+
+    // Root spans continue the trace from `TRACEPARENT`
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let traceon = traceon::from_env()
+        .unwrap()
+        .trace_ids()
+        .buffer(buffer.clone())
+        .clone();
+    tracing::subscriber::with_default(Registry::default().with(traceon), || {
+        let _span = tracing::info_span!("child process").entered();
+        tracing::info!("continued from the parent process");
+    });
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let record: Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(record["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(record["parent_span_id"], "00f067aa0ba902b7");
+
+    // The presets can be chosen too, with the Cloud Trace project after `google_cloud:`
+    unsafe { std::env::set_var("TRACEON_FORMAT", "ecs") };
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let traceon = traceon::from_env().unwrap().buffer(buffer.clone()).clone();
+    tracing::subscriber::with_default(Registry::default().with(traceon), || {
+        tracing::info!("in the elastic common schema");
+    });
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let record: Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(record["message"], "in the elastic common schema");
+    assert_eq!(record["log"]["level"], "info");
+    assert_eq!(record["ecs"]["version"], "8.11.0");
+
+    unsafe { std::env::set_var("TRACEON_FORMAT", "Google-Cloud:my-project") };
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let traceon = traceon::from_env()
+        .unwrap()
+        .trace_ids()
+        .buffer(buffer.clone())
+        .clone();
+    tracing::subscriber::with_default(Registry::default().with(traceon), || {
+        let _span = tracing::info_span!("request").entered();
+        tracing::warn!("in cloud logging");
+    });
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let record: Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(record["severity"], "WARNING");
+    assert_eq!(
+        record["logging.googleapis.com/trace"],
+        "projects/my-project/traces/4bf92f3577b34da6a3ce929d0e0e4736"
+    );
+
+    unsafe { std::env::set_var("TRACEON_FORMAT", "google_cloud:") };
+    let error = traceon::from_env().err().unwrap();
+    assert_eq!(error.variable, "TRACEON_FORMAT");
+
+    // Filter directives come from the chosen variable, and invalid ones are errors
+    unsafe { std::env::set_var("TRACEON_TEST_LOG", "error") };
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let guard = traceon::builder()
+        .json()
+        .filter("trace")
+        .filter_env("TRACEON_TEST_LOG")
+        .buffer(buffer.clone())
+        .on_thread();
+    tracing::warn!("filtered by the environment");
+    tracing::error!("kept by the environment");
+    drop(guard);
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let record: Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(record["message"], "kept by the environment");
+
+    unsafe { std::env::set_var("TRACEON_TEST_LOG", "app=loud") };
+    let error = traceon::builder()
+        .filter_env("TRACEON_TEST_LOG")
+        .try_on()
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .starts_with("invalid filter directives `app=loud` in TRACEON_TEST_LOG")
+    );
+
+    // FORCE_COLOR colors any writer, NO_COLOR wins over it, and TRACEON_COLOR wins over both
+    unsafe { std::env::set_var("TRACEON_FORMAT", "pretty") };
+    assert!(!colored(|buffer| traceon::builder().buffer(buffer).clone()));
+    unsafe { std::env::set_var("FORCE_COLOR", "1") };
+    assert!(colored(|buffer| traceon::builder().buffer(buffer).clone()));
+    unsafe { std::env::set_var("NO_COLOR", "1") };
+    assert!(!colored(|buffer| traceon::builder().buffer(buffer).clone()));
+    unsafe { std::env::set_var("TRACEON_COLOR", "always") };
+    assert!(colored(|buffer| traceon::from_env()
+        .unwrap()
+        .buffer(buffer)
+        .clone()));
+
+    // Invalid values are errors naming the variable
+    unsafe { std::env::set_var("TRACEON_TIMEZONE", "mars") };
+    let error = traceon::from_env().err().unwrap();
+    assert_eq!(error.variable, "TRACEON_TIMEZONE");
+    assert_eq!(error.value, "mars");
+    assert_eq!(
+        error.to_string(),
+        "invalid value `mars` for TRACEON_TIMEZONE, expected one of utc, local"
+    );
+}
+
+/// If a record written by the layer built with `layer` has any escape codes
+fn colored<L: Layer<Registry> + Send + Sync>(layer: impl FnOnce(Arc<Mutex<Vec<u8>>>) -> L) -> bool {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    tracing::subscriber::with_default(Registry::default().with(layer(buffer.clone())), || {
+        tracing::info!("maybe colored");
+    });
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    output.contains('\x1b')
+}
@@ -0,0 +1,71 @@
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+// The reload handle is returned from turning on the global default, which can only happen once per process
+#[test]
+fn reload_handle_changes_filter_and_formatting() {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let handle = traceon::builder()
+        .json()
+        .time(traceon::TimeFormat::None)
+        .filter("info")
+        .ignore_filter_env()
+        .buffer(buffer.clone())
+        .try_on()
+        .unwrap();
+
+    tracing::debug!("filtered out");
+    tracing::info!("before");
+    handle.filter("debug").unwrap();
+    tracing::debug!("after filter");
+    handle
+        .modify(|traceon| traceon.logfmt().message_key("msg"))
+        .unwrap();
+    tracing::info!("after modify");
+
+    let error = handle.filter("app=loud").unwrap_err();
+    assert!(matches!(error, traceon::ReloadError::Filter(_)));
+    tracing::debug!("still debug");
+
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    let messages: Vec<Value> = lines[..2]
+        .iter()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["message"].clone())
+        .collect();
+    assert_eq!(messages, ["before", "after filter"]);
+    assert_eq!(lines[2], r#"level=INFO msg="after modify""#);
+    assert_eq!(lines[3], r#"level=DEBUG msg="still debug""#);
+    assert_eq!(lines.len(), 4);
+
+    // Changing dedup writes the repeats from the windows that are still open before they're replaced
+    let window = std::time::Duration::from_secs(60);
+    handle
+        .modify(|traceon| {
+            traceon
+                .json()
+                .message_key("message")
+                .dedup(traceon::Dedup::new(window))
+        })
+        .unwrap();
+    for attempt in 0..3 {
+        tracing::warn!(attempt, "retrying");
+    }
+    handle
+        .modify(|traceon| traceon.dedup(traceon::Dedup::new(window)))
+        .unwrap();
+    tracing::warn!(attempt = 3, "retrying");
+
+    let output = String::from_utf8(buffer.lock().unwrap().to_vec()).unwrap();
+    let records: Vec<Value> = output
+        .lines()
+        .skip(4)
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[1]["attempt"], 2);
+    assert_eq!(records[1]["suppressed_count"], 2);
+    // The new window starts empty, so the next repeat is written
+    assert_eq!(records[2]["attempt"], 3);
+    assert!(records[2].get("suppressed_count").is_none());
+}